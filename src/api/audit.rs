@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header::CONTENT_TYPE, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+use tracing::error;
+use ulid::Ulid;
+
+use crate::{config::WEBHOOK_URL_PATH, SharedAppState};
+
+use super::{auth::CurrentUser, error::ApiError};
+
+/// Longest error message that is copied into an audit entry.
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// Body fields whose value is never written to the audit log, matched anywhere in the name.
+const SECRET_FIELDS: [&str; 3] = ["password", "auth", "token"];
+
+const REDACTED: &str = "<redacted>";
+
+/// Maps whose keys are kept but whose values are redacted, they routinely hold secrets.
+const SECRET_MAPS: [&str; 2] = ["env", "build_args"];
+
+/// Routes whose whole body is redacted, they carry passwords under any name (`current`, `new`).
+const SECRET_ROUTES: [&str; 1] = ["/auth/"];
+
+/// GET routes that change state and are recorded like any other mutation.
+const MUTATING_GETS: [&str; 1] = ["/projects/pull"];
+
+/// Who performed a request. Put in the response extensions by whatever authenticated it.
+#[derive(Debug, Clone)]
+pub struct Actor(pub String);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: Ulid,
+    pub time: DateTime<Utc>,
+    pub actor: String,
+    pub source_ip: String,
+    pub forwarded_for: Option<String>,
+    pub method: String,
+    pub target: String,
+    pub params: HashMap<String, String>,
+    /// JSON body of the request, with secrets redacted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    pub status: u16,
+    pub error: Option<String>,
+}
+
+/// Append-only log of every mutating request, stored as JSON lines.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    pub async fn new(folder: &Path, file: &Path) -> Result<Self> {
+        fs::create_dir_all(folder).await?;
+        Ok(Self {
+            path: folder.join(file),
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub async fn append(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Matching entries, newest first.
+    pub async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(content
+            .lines()
+            .rev()
+            .filter_map(|l| serde_json::from_str::<AuditEntry>(l).ok())
+            .filter(|e| query.matches(e))
+            .take(query.limit.unwrap_or(100))
+            .collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    actor: Option<String>,
+    /// Substring of the request path, e.g. a container or volume name
+    target: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    /// Only requests that did not succeed
+    failed: Option<bool>,
+    limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor.as_ref().is_none_or(|a| &entry.actor == a)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|t| entry.target.contains(t.as_str()))
+            && self.since.is_none_or(|s| entry.time >= s)
+            && self.until.is_none_or(|u| entry.time <= u)
+            && (!self.failed.unwrap_or(false) || entry.status >= 400)
+    }
+}
+
+/// Records every non-GET request, and the GETs in `MUTATING_GETS`, with its outcome into the audit log.
+pub async fn record_mutations(
+    State(state): State<SharedAppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        && !MUTATING_GETS.contains(&req.uri().path())
+    {
+        return next.run(req).await;
+    }
+
+    let method = req.method().to_string();
+    let target = req.uri().path().to_owned();
+    let params = Query::<HashMap<String, String>>::try_from_uri(req.uri())
+        .map(|q| q.0)
+        .unwrap_or_default();
+    let forwarded_for = forwarded_for(req.headers());
+
+    // The body has to be read to record it, the request is rebuilt from the bytes afterwards
+    let (body, response) = if is_json(req.headers()) {
+        let (parts, body) = req.into_parts();
+        match to_bytes(body, state.settings.max_body_bytes).await {
+            Ok(bytes) => {
+                let recorded = serde_json::from_slice::<Value>(&bytes)
+                    .ok()
+                    .map(|v| recorded_body(&target, v));
+                let req = Request::from_parts(parts, Body::from(bytes));
+                (recorded, next.run(req).await)
+            }
+            Err(e) => (
+                None,
+                ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, e.into()).into_response(),
+            ),
+        }
+    } else {
+        (None, next.run(req).await)
+    };
+
+    let status = response.status();
+    let actor = response
+        .extensions()
+        .get::<Actor>()
+        .map(|a| a.0.clone())
+        .unwrap_or_else(|| {
+            if target.starts_with(WEBHOOK_URL_PATH) {
+                "webhook".to_owned()
+            } else {
+                "anonymous".to_owned()
+            }
+        });
+
+    // Keep the error message, the body has to be rebuilt after reading it
+    let (response, error) = if status.is_client_error() || status.is_server_error() {
+        let (parts, body) = response.into_parts();
+        let bytes = to_bytes(body, usize::MAX).await.unwrap_or_default();
+        let error = serde_json::from_slice::<Value>(&bytes)
+            .ok()
+            .and_then(|v| v.get("error").and_then(Value::as_str).map(str::to_owned))
+            .or_else(|| Some(String::from_utf8_lossy(&bytes).into_owned()))
+            .filter(|e| !e.is_empty())
+            .map(|mut e| {
+                // Only the stored copy is cut, the client gets the whole body
+                let mut end = e.len().min(MAX_ERROR_BODY);
+                while !e.is_char_boundary(end) {
+                    end -= 1;
+                }
+                e.truncate(end);
+                e
+            });
+        (Response::from_parts(parts, Body::from(bytes)), error)
+    } else {
+        (response, None)
+    };
+
+    let entry = AuditEntry {
+        id: Ulid::new(),
+        time: Utc::now(),
+        actor,
+        source_ip: addr.ip().to_string(),
+        forwarded_for,
+        method,
+        target,
+        params,
+        body,
+        status: status.as_u16(),
+        error,
+    };
+    if let Err(e) = state.audit.append(&entry).await {
+        error!(?e, ?entry, "could not write audit entry");
+    }
+
+    response
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+/// Body as it is written to the audit log.
+fn recorded_body(target: &str, value: Value) -> Value {
+    if SECRET_ROUTES.iter().any(|r| target.starts_with(r)) {
+        Value::from(REDACTED)
+    } else {
+        redacted(value)
+    }
+}
+
+/// Replaces the value of every secret field, at any depth.
+fn redacted(mut value: Value) -> Value {
+    match &mut value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let key = key.to_lowercase();
+                *field = if SECRET_FIELDS.iter().any(|s| key.contains(s)) {
+                    Value::from(REDACTED)
+                } else if SECRET_MAPS.contains(&key.as_str()) {
+                    redacted_values(field.take())
+                } else {
+                    redacted(field.take())
+                };
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                *item = redacted(item.take());
+            }
+        }
+        _ => {}
+    }
+    value
+}
+
+/// Keeps the names of a map such as `env`, so the log still shows which variables were set.
+fn redacted_values(mut value: Value) -> Value {
+    match &mut value {
+        Value::Object(fields) => fields.values_mut().for_each(|v| *v = Value::from(REDACTED)),
+        _ => value = Value::from(REDACTED),
+    }
+    value
+}
+
+fn forwarded_for(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+}
+
+pub async fn audit_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    user.require_admin()?;
+    Ok(Json(state.audit.query(&query).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn password_changes_are_not_recorded() {
+        let body = json!({"current": "old secret", "new": "new secret"});
+        let recorded = recorded_body("/auth/password", body).to_string();
+        assert!(!recorded.contains("old secret"));
+        assert!(!recorded.contains("new secret"));
+    }
+
+    #[test]
+    fn env_values_are_redacted() {
+        let body = json!({
+            "image": "shop-main:4",
+            "env": {"DATABASE_URL": "postgres://shop:hunter2@db/shop"},
+            "build_args": {"NPM_TOKEN": "abc123"},
+            "registry": {"password": "pw", "username": "ci"},
+        });
+        let recorded = recorded_body("/containers", body);
+        assert_eq!(recorded["image"], "shop-main:4");
+        assert_eq!(recorded["env"]["DATABASE_URL"], REDACTED);
+        assert_eq!(recorded["build_args"]["NPM_TOKEN"], REDACTED);
+        assert_eq!(recorded["registry"]["password"], REDACTED);
+        assert_eq!(recorded["registry"]["username"], "ci");
+    }
+}
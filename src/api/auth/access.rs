@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use bollard::{errors::Error as DockerError, network::InspectNetworkOptions, Docker};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::error::ApiError,
+    config::{BRANCH_LABEL, PROJECT_LABEL},
+};
+
+use super::{CurrentUser, Role};
+
+/// How much a user may do with a granted project, each level includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// See the project, its builds and its docker resources
+    View,
+    /// Pull, run actions and start/stop/restart its containers
+    Deploy,
+    /// Remove the project and its docker resources
+    Manage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectGrant {
+    pub project: String,
+    /// Every branch of the project when not set
+    pub branch: Option<String>,
+    pub access: Access,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ResourceKind {
+    Container,
+    Image,
+    Volume,
+    Network,
+}
+
+impl CurrentUser {
+    /// Access to a project (branch), `None` means the user may not even see it.
+    pub fn project_access(&self, project: &str, branch: Option<&str>) -> Option<Access> {
+        if self.role.is_global() {
+            return Some(Access::Manage);
+        }
+        let access = self
+            .grants
+            .iter()
+            .filter(|g| {
+                g.project == project && (g.branch.is_none() || g.branch.as_deref() == branch)
+            })
+            .map(|g| g.access)
+            .max()?;
+
+        if self.role == Role::Viewer {
+            return Some(Access::View);
+        }
+        Some(access)
+    }
+
+    pub fn require_project(
+        &self,
+        project: &str,
+        branch: Option<&str>,
+        access: Access,
+    ) -> Result<(), ApiError> {
+        match self.project_access(project, branch) {
+            Some(a) if a >= access => Ok(()),
+            _ => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                anyhow!("no {access:?} access to project {project}"),
+            )),
+        }
+    }
+
+    /// Whether a docker resource is visible, based on its project ownership labels.
+    pub fn can_see(&self, labels: Option<&HashMap<String, String>>) -> bool {
+        self.resource_access(labels).is_some()
+    }
+
+    fn resource_access(&self, labels: Option<&HashMap<String, String>>) -> Option<Access> {
+        if self.role.is_global() {
+            return Some(Access::Manage);
+        }
+        let labels = labels?;
+        self.project_access(
+            labels.get(PROJECT_LABEL)?,
+            labels.get(BRANCH_LABEL).map(String::as_str),
+        )
+    }
+
+    /// Checks access to a single docker resource by inspecting its labels.
+    pub async fn require_resource(
+        &self,
+        docker: &Docker,
+        kind: ResourceKind,
+        id: &str,
+        access: Access,
+    ) -> Result<(), ApiError> {
+        if self.role.is_global() {
+            return Ok(());
+        }
+
+        let labels = match kind {
+            ResourceKind::Container => docker
+                .inspect_container(id, None)
+                .await?
+                .config
+                .and_then(|c| c.labels),
+            ResourceKind::Image => docker
+                .inspect_image(id)
+                .await?
+                .config
+                .and_then(|c| c.labels),
+            ResourceKind::Volume => Some(docker.inspect_volume(id).await?.labels),
+            ResourceKind::Network => {
+                docker
+                    .inspect_network(id, None::<InspectNetworkOptions<String>>)
+                    .await?
+                    .labels
+            }
+        };
+
+        match self.resource_access(labels.as_ref()) {
+            Some(a) if a >= access => Ok(()),
+            _ => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                anyhow!("no {access:?} access to {kind:?} {id}"),
+            )),
+        }
+    }
+
+    /// Checks that a user may give an image the name `target` by tagging, building or committing,
+    /// which takes the name from the image that had it. Others than global users need Deploy access
+    /// to that image or, for a new name, have to name it after the project in `labels` like its builds are.
+    pub async fn require_image_name(
+        &self,
+        docker: &Docker,
+        target: &str,
+        labels: Option<&HashMap<String, String>>,
+    ) -> Result<(), ApiError> {
+        if self.role.is_global() {
+            return Ok(());
+        }
+
+        let allowed = match docker.inspect_image(target).await {
+            Ok(image) => {
+                let current = image.config.and_then(|c| c.labels);
+                self.resource_access(current.as_ref()) >= Some(Access::Deploy)
+            }
+            Err(DockerError::DockerResponseServerError {
+                status_code: 404, ..
+            }) => self.may_name_image(target, labels),
+            Err(e) => return Err(e.into()),
+        };
+        if !allowed {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                anyhow!("no access to the image name {target}"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether `target` is named `<project>-<branch>` (optionally under a registry path) after a project
+    /// in `labels` the user can deploy.
+    fn may_name_image(&self, target: &str, labels: Option<&HashMap<String, String>>) -> bool {
+        let Some(labels) = labels else {
+            return false;
+        };
+        let (Some(project), Some(branch)) = (labels.get(PROJECT_LABEL), labels.get(BRANCH_LABEL))
+        else {
+            return false;
+        };
+        let name = target.rsplit('/').next().unwrap_or(target);
+        let repository = name.split([':', '@']).next().unwrap_or(name);
+        repository == format!("{project}-{branch}")
+            && self.project_access(project, Some(branch.as_str())) >= Some(Access::Deploy)
+    }
+
+    /// For operations spanning every resource, such as prunes.
+    pub fn require_global(&self) -> Result<(), ApiError> {
+        if !self.role.is_global() {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                anyhow!("only admins and operators can do this"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::Scope;
+
+    fn user(role: Role, grants: Vec<ProjectGrant>) -> CurrentUser {
+        CurrentUser {
+            username: "test".to_owned(),
+            scopes: role.scopes(),
+            role,
+            grants,
+            token: None,
+        }
+    }
+
+    fn grant(project: &str, branch: Option<&str>, access: Access) -> ProjectGrant {
+        ProjectGrant {
+            project: project.to_owned(),
+            branch: branch.map(str::to_owned),
+            access,
+        }
+    }
+
+    fn labels(project: &str, branch: &str) -> HashMap<String, String> {
+        HashMap::from([
+            (PROJECT_LABEL.to_owned(), project.to_owned()),
+            (BRANCH_LABEL.to_owned(), branch.to_owned()),
+        ])
+    }
+
+    #[test]
+    fn global_roles_manage_everything() {
+        for role in [Role::Admin, Role::Operator] {
+            let u = user(role, vec![]);
+            assert_eq!(u.project_access("any", Some("main")), Some(Access::Manage));
+            assert!(u.can_see(None));
+            assert!(u.require_global().is_ok());
+        }
+    }
+
+    #[test]
+    fn members_only_reach_granted_branches() {
+        let u = user(
+            Role::Member,
+            vec![
+                grant("web", Some("main"), Access::Deploy),
+                grant("api", None, Access::View),
+            ],
+        );
+        assert_eq!(u.project_access("web", Some("main")), Some(Access::Deploy));
+        assert_eq!(u.project_access("web", Some("dev")), None);
+        assert_eq!(u.project_access("api", Some("dev")), Some(Access::View));
+        assert_eq!(u.project_access("other", None), None);
+        assert!(u
+            .require_project("web", Some("main"), Access::Deploy)
+            .is_ok());
+        assert!(u
+            .require_project("web", Some("main"), Access::Manage)
+            .is_err());
+        assert!(u.require_global().is_err());
+    }
+
+    #[test]
+    fn members_manage_with_a_manage_grant() {
+        let u = user(
+            Role::Member,
+            vec![
+                grant("web", Some("main"), Access::Manage),
+                grant("api", None, Access::Deploy),
+            ],
+        );
+        // Removing a project and setting its push_to take both
+        assert!(u.has_scope(Scope::ProjectsWrite));
+        assert!(u
+            .require_project("web", Some("main"), Access::Manage)
+            .is_ok());
+        assert!(u
+            .require_project("api", Some("main"), Access::Manage)
+            .is_err());
+        // Creating projects stays with admins and operators
+        assert!(u.require_global().is_err());
+    }
+
+    #[test]
+    fn highest_matching_grant_wins() {
+        let u = user(
+            Role::Member,
+            vec![
+                grant("web", None, Access::View),
+                grant("web", Some("main"), Access::Manage),
+            ],
+        );
+        assert_eq!(u.project_access("web", Some("main")), Some(Access::Manage));
+        assert_eq!(u.project_access("web", Some("dev")), Some(Access::View));
+    }
+
+    #[test]
+    fn viewers_are_capped_at_view() {
+        let u = user(Role::Viewer, vec![grant("web", None, Access::Manage)]);
+        assert_eq!(u.project_access("web", Some("main")), Some(Access::View));
+        assert!(u
+            .require_project("web", Some("main"), Access::Deploy)
+            .is_err());
+    }
+
+    #[test]
+    fn resources_follow_their_labels() {
+        let u = user(
+            Role::Member,
+            vec![grant("web", Some("main"), Access::Deploy)],
+        );
+        assert!(u.can_see(Some(&labels("web", "main"))));
+        assert!(!u.can_see(Some(&labels("web", "dev"))));
+        assert!(!u.can_see(Some(&HashMap::new())));
+        assert!(!u.can_see(None));
+    }
+
+    #[test]
+    fn new_image_names_follow_the_project() {
+        let u = user(
+            Role::Member,
+            vec![
+                grant("web", Some("main"), Access::Deploy),
+                grant("api", None, Access::View),
+            ],
+        );
+        let web = labels("web", "main");
+        assert!(u.may_name_image("web-main", Some(&web)));
+        assert!(u.may_name_image("web-main:4", Some(&web)));
+        assert!(u.may_name_image("localhost:5000/team/web-main:4", Some(&web)));
+        assert!(!u.may_name_image("postgres:latest", Some(&web)));
+        assert!(!u.may_name_image("web-dev:1", Some(&web)));
+        assert!(!u.may_name_image("api-main:1", Some(&labels("api", "main"))));
+        assert!(!u.may_name_image("web-main:4", None));
+    }
+}
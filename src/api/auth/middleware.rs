@@ -0,0 +1,121 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::CookieJar;
+
+use crate::{
+    api::{audit::Actor, error::ApiError},
+    config::SESSION_COOKIE,
+    SharedAppState,
+};
+
+use super::{tokens::TokenKind, CurrentUser, Scope};
+
+/// Resolves the session cookie to a user that still exists.
+pub async fn authenticate(state: &SharedAppState, jar: &CookieJar) -> Option<CurrentUser> {
+    let token = jar.get(SESSION_COOKIE)?;
+    let session = state.sessions.get(token.value()).await?;
+    let user = state.users.get_owned(&session.username).await?;
+    Some(CurrentUser {
+        username: user.username,
+        scopes: user.role.scopes(),
+        role: user.role,
+        grants: user.grants,
+        token: None,
+    })
+}
+
+/// Resolves a bearer API token. Personal tokens never get more scopes than their owner's role.
+pub async fn authenticate_token(state: &SharedAppState, token: &str) -> Option<CurrentUser> {
+    let token = state.tokens.verify(token).await?;
+    let (role, grants) = match token.kind() {
+        TokenKind::Personal => {
+            let owner = state.users.get_owned(token.owner()).await?;
+            (owner.role, owner.grants)
+        }
+        TokenKind::Service => (token.service_role(), Vec::new()),
+    };
+    let allowed = role.scopes();
+    let scopes = token
+        .scopes()
+        .iter()
+        .copied()
+        .filter(|s| allowed.contains(&Scope::Admin) || allowed.contains(s))
+        .collect();
+
+    Some(CurrentUser {
+        username: token.principal(),
+        role,
+        grants,
+        scopes,
+        token: Some(token.id()),
+    })
+}
+
+/// Reads `Authorization: Bearer`, or the `access_token` query parameter on WebSocket upgrades
+/// since browsers can not set headers there.
+fn bearer_token(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    if let Some(value) = headers.get(header::AUTHORIZATION) {
+        return value
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")
+            .map(|t| t.trim().to_owned());
+    }
+
+    let is_upgrade = headers
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    if !is_upgrade {
+        return None;
+    }
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("access_token="))
+        .map(str::to_owned)
+}
+
+/// Rejects API and WebSocket requests without a valid session or API token.
+pub async fn require_session(
+    State(state): State<SharedAppState>,
+    jar: CookieJar,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let user = match bearer_token(req.headers(), req.uri()) {
+        Some(token) => authenticate_token(&state, &token)
+            .await
+            .ok_or(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                anyhow!("invalid, expired or revoked api token"),
+            ))?,
+        None => authenticate(&state, &jar).await.ok_or(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("not logged in"),
+        ))?,
+    };
+    let actor = Actor(user.username.clone());
+    req.extensions_mut().insert(user);
+
+    let mut response = next.run(req).await;
+    response.extensions_mut().insert(actor);
+    Ok(response)
+}
+
+/// Sends browsers without a valid session to the login page.
+pub async fn redirect_to_login(
+    State(state): State<SharedAppState>,
+    jar: CookieJar,
+    req: Request,
+    next: Next,
+) -> Response {
+    match authenticate(&state, &jar).await {
+        Some(_) => next.run(req).await,
+        None => Redirect::temporary("/login").into_response(),
+    }
+}
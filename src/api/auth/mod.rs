@@ -0,0 +1,257 @@
+use anyhow::{anyhow, bail, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ulid::Ulid;
+
+use self::access::ProjectGrant;
+use super::error::ApiError;
+
+pub mod access;
+pub mod middleware;
+pub mod routes;
+pub mod session;
+pub mod tokens;
+pub mod user_store;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including users and tokens
+    Admin,
+    /// All projects and docker resources
+    #[serde(alias = "user")]
+    Operator,
+    /// Only the projects (and their resources) granted to them, can not prune
+    Member,
+    /// Read-only access to the projects granted to them
+    Viewer,
+}
+
+impl Role {
+    /// Scopes a session of this role is allowed to use.
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Role::Admin => vec![Scope::Admin],
+            Role::Operator => vec![
+                Scope::ReadOnly,
+                Scope::ContainersWrite,
+                Scope::ResourcesWrite,
+                Scope::ProjectsDeploy,
+                Scope::ProjectsWrite,
+            ],
+            // What they may do to a project is up to its grant, e.g. a manage grant to remove it
+            Role::Member => vec![
+                Scope::ReadOnly,
+                Scope::ContainersWrite,
+                Scope::ResourcesWrite,
+                Scope::ProjectsDeploy,
+                Scope::ProjectsWrite,
+            ],
+            Role::Viewer => vec![Scope::ReadOnly],
+        }
+    }
+
+    /// Whether this role sees every project and docker resource, instead of only granted ones.
+    pub fn is_global(&self) -> bool {
+        matches!(self, Role::Admin | Role::Operator)
+    }
+}
+
+/// What a session or API token may do. Everything authenticated may read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "read-only")]
+    ReadOnly,
+    /// Start, stop, restart and remove containers
+    #[serde(rename = "containers:write")]
+    ContainersWrite,
+    /// Remove and prune images, volumes and networks
+    #[serde(rename = "resources:write")]
+    ResourcesWrite,
+    /// Pull and run actions on existing projects
+    #[serde(rename = "projects:deploy")]
+    ProjectsDeploy,
+    /// Create, remove and configure projects. Creating one is reserved for admins and operators
+    #[serde(rename = "projects:write")]
+    ProjectsWrite,
+    /// Everything, including user and token management
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct User {
+    username: String,
+    password_hash: String,
+    role: Role,
+    #[serde(default)]
+    grants: Vec<ProjectGrant>,
+    created_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn new(username: &str, password: &str, role: Role) -> Result<Self> {
+        if username.is_empty() || username.contains('/') || username.contains('\\') {
+            bail!("invalid username");
+        }
+        Ok(Self {
+            username: username.to_owned(),
+            password_hash: hash_password(password)?,
+            role,
+            grants: Vec::new(),
+            created_at: Utc::now(),
+        })
+    }
+
+    pub fn info(&self) -> UserInfo {
+        UserInfo {
+            username: self.username.clone(),
+            role: self.role,
+            grants: self.grants.clone(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// User as exposed through the API, without the password hash.
+#[derive(Debug, Serialize, Clone)]
+pub struct UserInfo {
+    username: String,
+    role: Role,
+    grants: Vec<ProjectGrant>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Users(Vec<User>);
+
+impl Users {
+    pub fn get_owned(&self, username: &str) -> Option<User> {
+        self.0.iter().find(|v| v.username == username).cloned()
+    }
+
+    pub fn get_mut(&mut self, username: &str) -> Option<&mut User> {
+        self.0.iter_mut().find(|v| v.username == username)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn insert(&mut self, user: User) -> Result<()> {
+        if self.get_owned(&user.username).is_some() {
+            bail!("user already exists");
+        }
+        self.0.push(user);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, username: &str) -> Result<()> {
+        for (i, item) in self.0.iter().enumerate() {
+            if item.username == username {
+                self.0.remove(i);
+                return Ok(());
+            }
+        }
+        bail!("user does not exist");
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewUser {
+    username: String,
+    password: String,
+    role: Role,
+}
+
+/// The logged in user or API token, inserted into the request extensions by the auth middleware.
+#[derive(Debug, Clone)]
+pub struct CurrentUser {
+    pub username: String,
+    pub role: Role,
+    pub grants: Vec<ProjectGrant>,
+    pub scopes: Vec<Scope>,
+    /// Set when authenticated with an API token instead of a session.
+    pub token: Option<Ulid>,
+}
+
+impl CurrentUser {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+
+    pub fn require_scope(&self, scope: Scope) -> Result<(), ApiError> {
+        if !self.has_scope(scope) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                anyhow!("missing scope {}", json!(scope)),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn require_admin(&self) -> Result<(), ApiError> {
+        self.require_scope(Scope::Admin)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<CurrentUser>()
+            .cloned()
+            .ok_or(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                anyhow!("not logged in"),
+            ))
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    if password.len() < 8 {
+        bail!("password must be at least 8 characters");
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("could not hash password: {e}"))?
+        .to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn generate_secret(len: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
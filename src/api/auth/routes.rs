@@ -0,0 +1,273 @@
+use anyhow::anyhow;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::{Extension, Json};
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use ulid::Ulid;
+
+use crate::api::audit::Actor;
+use crate::api::error::{ApiError, ApiErrorVariant};
+use crate::config::SESSION_COOKIE;
+use crate::SharedAppState;
+
+use super::access::ProjectGrant;
+use super::tokens::{CreatedToken, NewToken, TokenInfo, TokenKind};
+use super::{Credentials, CurrentUser, NewUser, Role, Scope, User, UserInfo};
+
+const LOGIN_PAGE: &str = include_str!("login.html");
+
+pub async fn login_page() -> Html<&'static str> {
+    Html(LOGIN_PAGE)
+}
+
+pub async fn login_route(
+    State(state): State<SharedAppState>,
+    jar: CookieJar,
+    Json(credentials): Json<Credentials>,
+) -> Result<(Extension<Actor>, CookieJar, Json<UserInfo>), ApiError> {
+    let user = match state
+        .users
+        .verify(&credentials.username, &credentials.password)
+        .await
+    {
+        Some(u) => u,
+        None => {
+            warn!(username = credentials.username, "failed login attempt");
+            return Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                anyhow!("invalid username or password"),
+            ));
+        }
+    };
+
+    let token = state.sessions.create(&user.username).await;
+    let cookie = Cookie::build((SESSION_COOKIE, token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(state.settings.secure_cookies);
+    info!(username = user.username, "user logged in");
+
+    Ok((
+        Extension(Actor(user.username.clone())),
+        jar.add(cookie),
+        Json(user.info()),
+    ))
+}
+
+pub async fn logout_route(
+    State(state): State<SharedAppState>,
+    jar: CookieJar,
+) -> (CookieJar, StatusCode) {
+    if let Some(token) = jar.get(SESSION_COOKIE) {
+        state.sessions.remove(token.value()).await;
+    }
+    (
+        jar.remove(Cookie::build(SESSION_COOKIE).path("/")),
+        StatusCode::OK,
+    )
+}
+
+pub async fn me_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+) -> Result<Json<UserInfo>, ApiError> {
+    let user = state
+        .users
+        .get_owned(&user.username)
+        .await
+        .ok_or(ApiError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("user not found"),
+        ))?;
+    Ok(Json(user.info()))
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    current: String,
+    new: String,
+}
+
+/// Ends every other session of the user and revokes their personal tokens, the current session stays logged in.
+pub async fn change_password_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    jar: CookieJar,
+    Json(change): Json<PasswordChange>,
+) -> Result<StatusCode, ApiError> {
+    if state
+        .users
+        .verify(&user.username, &change.current)
+        .await
+        .is_none()
+    {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("current password is incorrect"),
+        ));
+    }
+    state
+        .users
+        .set_password(&user.username, &change.new)
+        .await
+        .to_apierror(StatusCode::BAD_REQUEST)?;
+    let current = jar.get(SESSION_COOKIE).map(|c| c.value());
+    state.sessions.remove_others(&user.username, current).await;
+    let tokens = state.tokens.revoke_personal(&user.username).await?;
+    info!(username = user.username, tokens, "changed password");
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn list_users_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+) -> Result<Json<Vec<UserInfo>>, ApiError> {
+    user.require_admin()?;
+    let users = state.users.get_all().await.0;
+
+    Ok(Json(users.iter().map(User::info).collect()))
+}
+
+pub async fn new_user_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Json(new_user): Json<NewUser>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    user.require_admin()?;
+    let created = User::new(&new_user.username, &new_user.password, new_user.role)
+        .to_apierror(StatusCode::BAD_REQUEST)?;
+    state
+        .users
+        .insert(created)
+        .await
+        .to_apierror(StatusCode::CONFLICT)?;
+    info!(by = user.username, username = new_user.username, role = ?new_user.role, "created user");
+
+    Ok((StatusCode::CREATED, Json(json!({}))))
+}
+
+#[derive(Deserialize)]
+pub struct UserAccess {
+    role: Role,
+    #[serde(default)]
+    grants: Vec<ProjectGrant>,
+}
+
+pub async fn user_access_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(username): Path<String>,
+    Json(access): Json<UserAccess>,
+) -> Result<StatusCode, ApiError> {
+    user.require_admin()?;
+    if user.username == username && access.role != Role::Admin {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("cannot remove your own admin role"),
+        ));
+    }
+    state
+        .users
+        .set_access(&username, access.role, access.grants)
+        .await
+        .to_apierror(StatusCode::NOT_FOUND)?;
+    info!(by = user.username, username, role = ?access.role, "changed user access");
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn remove_user_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(username): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    user.require_admin()?;
+    if user.username == username {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("cannot remove yourself"),
+        ));
+    }
+    state
+        .users
+        .remove(&username)
+        .await
+        .to_apierror(StatusCode::NOT_FOUND)?;
+    state.sessions.remove_user(&username).await;
+    let tokens = state.tokens.remove_owner(&username).await?;
+    info!(by = user.username, username, tokens, "removed user");
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn list_tokens_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+) -> Result<Json<Vec<TokenInfo>>, ApiError> {
+    let tokens = state.tokens.get_all().await.0;
+    let is_admin = user.has_scope(Scope::Admin);
+
+    Ok(Json(
+        tokens
+            .into_iter()
+            .filter(|t| is_admin || t.owner() == user.username)
+            .map(|t| t.info())
+            .collect(),
+    ))
+}
+
+pub async fn new_token_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Json(new_token): Json<NewToken>,
+) -> Result<(StatusCode, Json<CreatedToken>), ApiError> {
+    // Tokens can not mint new tokens, unless they are admin tokens
+    if new_token.kind == TokenKind::Service || user.token.is_some() {
+        user.require_admin()?;
+    }
+    if let Some(scope) = new_token.scopes.iter().find(|s| !user.has_scope(**s)) {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            anyhow!("can not grant scope {} you do not have", json!(scope)),
+        ));
+    }
+
+    let created = state
+        .tokens
+        .create(&user.username, new_token)
+        .await
+        .to_apierror(StatusCode::BAD_REQUEST)?;
+    info!(by = user.username, id = %created.info.id(), name = created.info.name(), "created api token");
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+pub async fn revoke_token_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    let token = state.tokens.get_owned(id).await.ok_or(ApiError::new(
+        StatusCode::NOT_FOUND,
+        anyhow!("token does not exist"),
+    ))?;
+    if token.owner() != user.username {
+        user.require_admin()?;
+    }
+
+    state
+        .tokens
+        .revoke(id)
+        .await
+        .to_apierror(StatusCode::CONFLICT)?;
+    info!(by = user.username, %id, "revoked api token");
+
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::RwLock;
+
+use super::generate_secret;
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub username: String,
+    pub expires: DateTime<Utc>,
+}
+
+/// In-memory login sessions, keyed by the random token stored in the session cookie.
+/// Sessions do not survive a restart.
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    inner: Arc<RwLock<HashMap<String, Session>>>,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    /// Starts a new session and returns its token.
+    pub async fn create(&self, username: &str) -> String {
+        let token = generate_secret(48);
+        let now = Utc::now();
+        let session = Session {
+            username: username.to_owned(),
+            expires: now + self.ttl,
+        };
+
+        let mut sessions = self.inner.write().await;
+        sessions.retain(|_, s| s.expires > now);
+        sessions.insert(token.clone(), session);
+        token
+    }
+
+    pub async fn get(&self, token: &str) -> Option<Session> {
+        self.inner
+            .read()
+            .await
+            .get(token)
+            .filter(|s| s.expires > Utc::now())
+            .cloned()
+    }
+
+    pub async fn remove(&self, token: &str) {
+        self.inner.write().await.remove(token);
+    }
+
+    /// Ends every session of a user, e.g. after a removal.
+    pub async fn remove_user(&self, username: &str) {
+        self.inner
+            .write()
+            .await
+            .retain(|_, s| s.username != username);
+    }
+
+    /// Ends every session of a user but `keep`, e.g. after a password change.
+    pub async fn remove_others(&self, username: &str, keep: Option<&str>) {
+        self.inner
+            .write()
+            .await
+            .retain(|token, s| s.username != username || Some(token.as_str()) == keep);
+    }
+}
@@ -0,0 +1,262 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, RwLock};
+use ulid::Ulid;
+
+use crate::store::Store;
+
+use super::{generate_secret, Role, Scope};
+
+const TOKEN_PREFIX: &str = "sct_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    /// Acts on behalf of the user that created it.
+    Personal,
+    /// Belongs to an automation (CI, scripts), can only be created by admins.
+    Service,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiToken {
+    id: Ulid,
+    name: String,
+    kind: TokenKind,
+    /// Creator of the token, personal tokens act as this user.
+    owner: String,
+    scopes: Vec<Scope>,
+    /// Hex encoded SHA-256 of the secret part, the secret itself is never stored.
+    secret_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn id(&self) -> Ulid {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> TokenKind {
+        self.kind
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|e| e > Utc::now())
+    }
+
+    /// Name of the principal this token authenticates as.
+    pub fn principal(&self) -> String {
+        match self.kind {
+            TokenKind::Personal => self.owner.clone(),
+            TokenKind::Service => format!("service:{}", self.name),
+        }
+    }
+
+    pub fn info(&self) -> TokenInfo {
+        TokenInfo {
+            id: self.id,
+            name: self.name.clone(),
+            kind: self.kind,
+            owner: self.owner.clone(),
+            scopes: self.scopes.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            revoked_at: self.revoked_at,
+        }
+    }
+
+    /// Role of a service token, derived from its scopes.
+    pub fn service_role(&self) -> Role {
+        if self.scopes.contains(&Scope::Admin) {
+            Role::Admin
+        } else {
+            Role::Operator
+        }
+    }
+}
+
+/// Token as exposed through the API, without the secret hash.
+#[derive(Debug, Serialize, Clone)]
+pub struct TokenInfo {
+    id: Ulid,
+    name: String,
+    kind: TokenKind,
+    owner: String,
+    scopes: Vec<Scope>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl TokenInfo {
+    pub fn id(&self) -> Ulid {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewToken {
+    pub name: String,
+    pub kind: TokenKind,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<u32>,
+}
+
+/// Returned once on creation, the plaintext token can not be retrieved afterwards.
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    pub token: String,
+    pub info: TokenInfo,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ApiTokens(pub Vec<ApiToken>);
+
+impl ApiTokens {
+    pub fn get_owned(&self, id: Ulid) -> Option<ApiToken> {
+        self.0.iter().find(|v| v.id == id).cloned()
+    }
+
+    pub fn get_mut(&mut self, id: Ulid) -> Option<&mut ApiToken> {
+        self.0.iter_mut().find(|v| v.id == id)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenStore {
+    inner: Arc<RwLock<ApiTokens>>,
+    fs_store: Arc<Mutex<Store>>,
+}
+
+impl TokenStore {
+    pub async fn new(store: Store) -> Self {
+        let mut content = ApiTokens::default();
+        if let Ok(store_content) = store.read::<Vec<ApiToken>>().await {
+            content.0 = store_content;
+        };
+        Self {
+            fs_store: Arc::new(Mutex::new(store)),
+            inner: Arc::new(RwLock::new(content)),
+        }
+    }
+
+    pub async fn create(&self, owner: &str, new: NewToken) -> Result<CreatedToken> {
+        if new.name.is_empty() {
+            bail!("token name can not be empty");
+        }
+        if new.scopes.is_empty() {
+            bail!("token needs at least one scope");
+        }
+
+        let id = Ulid::new();
+        let secret = generate_secret(40);
+        let info = ApiToken {
+            id,
+            name: new.name,
+            kind: new.kind,
+            owner: owner.to_owned(),
+            scopes: new.scopes,
+            secret_hash: hash_secret(&secret),
+            created_at: Utc::now(),
+            expires_at: new
+                .expires_in_days
+                .map(|d| Utc::now() + chrono::Duration::days(d.into())),
+            revoked_at: None,
+        };
+
+        let mut store = self.inner.write().await;
+        store.0.push(info.clone());
+        self.fs_store.lock().await.write(&store.0).await?;
+
+        Ok(CreatedToken {
+            token: format!("{TOKEN_PREFIX}{id}_{secret}"),
+            info: info.info(),
+        })
+    }
+
+    /// Looks up an active token by its plaintext value.
+    pub async fn verify(&self, token: &str) -> Option<ApiToken> {
+        let (id, secret) = token.strip_prefix(TOKEN_PREFIX)?.split_once('_')?;
+        let id = Ulid::from_string(id).ok()?;
+        let found = self.inner.read().await.get_owned(id)?;
+
+        (found.is_active() && found.secret_hash == hash_secret(secret)).then_some(found)
+    }
+
+    pub async fn revoke(&self, id: Ulid) -> Result<()> {
+        let mut store = self.inner.write().await;
+
+        match store.get_mut(id) {
+            Some(t) if t.revoked_at.is_none() => t.revoked_at = Some(Utc::now()),
+            Some(_) => bail!("token is already revoked"),
+            None => bail!("token does not exist"),
+        }
+        self.fs_store.lock().await.write(&store.0).await?;
+        Ok(())
+    }
+
+    /// Revokes the active personal tokens of a user, they act with the user's access.
+    pub async fn revoke_personal(&self, owner: &str) -> Result<usize> {
+        let mut store = self.inner.write().await;
+        let now = Utc::now();
+        let mut revoked = 0;
+        for token in store
+            .0
+            .iter_mut()
+            .filter(|t| t.owner == owner && t.kind == TokenKind::Personal && t.revoked_at.is_none())
+        {
+            token.revoked_at = Some(now);
+            revoked += 1;
+        }
+        if revoked > 0 {
+            self.fs_store.lock().await.write(&store.0).await?;
+        }
+        Ok(revoked)
+    }
+
+    /// Deletes every token created by a user, so they can not come back to life with a new account of the same name.
+    pub async fn remove_owner(&self, owner: &str) -> Result<usize> {
+        let mut store = self.inner.write().await;
+        let before = store.0.len();
+        store.0.retain(|t| t.owner != owner);
+        let removed = before - store.0.len();
+        if removed > 0 {
+            self.fs_store.lock().await.write(&store.0).await?;
+        }
+        Ok(removed)
+    }
+
+    pub async fn get_owned(&self, id: Ulid) -> Option<ApiToken> {
+        self.inner.read().await.get_owned(id)
+    }
+
+    pub async fn get_all(&self) -> ApiTokens {
+        self.inner.read().await.clone()
+    }
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
@@ -0,0 +1,168 @@
+use std::sync::{Arc, OnceLock};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{Mutex, RwLock},
+};
+use tracing::{error, warn};
+
+use crate::{
+    config::{ADMIN_PASSWORD_FILE, BOOTSTRAP_ADMIN},
+    store::Store,
+};
+
+use super::access::ProjectGrant;
+use super::{generate_secret, hash_password, verify_password, Role, User, Users};
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone)]
+pub struct UserStore {
+    inner: Arc<RwLock<Users>>,
+    fs_store: Arc<Mutex<Store>>,
+}
+
+impl UserStore {
+    pub async fn new(store: Store) -> Self {
+        let mut content = Users::default();
+        if let Ok(store_content) = store.read::<Vec<User>>().await {
+            content.0 = store_content;
+        };
+        Self {
+            fs_store: Arc::new(Mutex::new(store)),
+            inner: Arc::new(RwLock::new(content)),
+        }
+    }
+
+    /// Creates the first admin account when no users exist yet.
+    /// If no password is configured, a random one is generated and written to `ADMIN_PASSWORD_FILE`.
+    pub async fn bootstrap_admin(&self, password: Option<String>) -> Result<()> {
+        if !self.inner.read().await.is_empty() {
+            return Ok(());
+        }
+
+        let generated = password.is_none();
+        let password = password.unwrap_or_else(|| generate_secret(24));
+        // Written before the account exists, so a failure doesn't leave an admin nobody knows the password of
+        if generated {
+            write_private(ADMIN_PASSWORD_FILE, &password).await?;
+        }
+        self.insert(User::new(BOOTSTRAP_ADMIN, &password, Role::Admin)?)
+            .await?;
+
+        if generated {
+            warn!(
+                username = BOOTSTRAP_ADMIN,
+                file = ADMIN_PASSWORD_FILE,
+                "no users found, created bootstrap admin. Its password is in the file, change it after logging in and delete the file"
+            );
+        } else {
+            warn!(
+                username = BOOTSTRAP_ADMIN,
+                "no users found, created bootstrap admin from SERVCUR_ADMIN_PASSWORD"
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn get_owned(&self, username: &str) -> Option<User> {
+        self.inner.read().await.get_owned(username)
+    }
+
+    /// Returns the user if the password matches.
+    pub async fn verify(&self, username: &str, password: &str) -> Option<User> {
+        let user = self.get_owned(username).await;
+        let password = password.to_owned();
+        // Unknown users are checked against a dummy hash, so they take as long as a wrong password
+        let hash = match &user {
+            Some(user) => user.password_hash.clone(),
+            None => dummy_hash().to_owned(),
+        };
+        // Argon2 is deliberately slow, keep it off the async workers
+        let valid = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+            .await
+            .unwrap_or(false);
+        user.filter(|_| valid)
+    }
+
+    pub async fn insert(&self, user: User) -> Result<()> {
+        let mut store = self.inner.write().await;
+
+        store.insert(user)?;
+        self.fs_store.lock().await.write(&store.0).await?;
+        Ok(())
+    }
+
+    pub async fn set_password(&self, username: &str, password: &str) -> Result<()> {
+        let hash = hash_password(password)?;
+        let mut store = self.inner.write().await;
+
+        match store.get_mut(username) {
+            Some(user) => user.password_hash = hash,
+            None => bail!("user does not exist"),
+        }
+        self.fs_store.lock().await.write(&store.0).await?;
+        Ok(())
+    }
+
+    pub async fn set_access(
+        &self,
+        username: &str,
+        role: Role,
+        grants: Vec<ProjectGrant>,
+    ) -> Result<()> {
+        let mut store = self.inner.write().await;
+
+        match store.get_mut(username) {
+            Some(user) => {
+                user.role = role;
+                user.grants = grants;
+            }
+            None => bail!("user does not exist"),
+        }
+        self.fs_store.lock().await.write(&store.0).await?;
+        Ok(())
+    }
+
+    pub async fn remove(&self, username: &str) -> Result<()> {
+        let mut store = self.inner.write().await;
+
+        store.remove(username)?;
+        self.fs_store.lock().await.write(&store.0).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_all(&self) -> Users {
+        self.inner.read().await.clone()
+    }
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        hash_password(&generate_secret(24)).unwrap_or_else(|e| {
+            error!(%e, "could not create the dummy password hash");
+            String::new()
+        })
+    })
+}
+
+/// Creates the file readable and writable by the owner only.
+async fn write_private(path: &str, content: &str) -> Result<()> {
+    if let Some(folder) = std::path::Path::new(path).parent() {
+        fs::create_dir_all(folder).await?;
+    }
+    // A leftover file could have looser permissions, which a plain overwrite would keep
+    if fs::try_exists(path).await? {
+        fs::remove_file(path).await?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .await?;
+    file.write_all(content.as_bytes()).await?;
+    file.write_all(b"\n").await?;
+    Ok(file.sync_all().await?)
+}
@@ -1,34 +1,268 @@
+use std::collections::HashMap;
+
 use axum::extract::Path;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use axum::{extract::State, http::StatusCode};
+use axum::{body::Body, extract::State, http::StatusCode};
+use axum_extra::extract::Query;
 
+use anyhow::anyhow;
 use bollard::container::{
-    ListContainersOptions, RemoveContainerOptions, RestartContainerOptions, StartContainerOptions,
-    StopContainerOptions,
+    Config, CreateContainerOptions, InspectContainerOptions, KillContainerOptions,
+    ListContainersOptions, PruneContainersOptions, RemoveContainerOptions, RenameContainerOptions,
+    RestartContainerOptions, StartContainerOptions, StopContainerOptions,
 };
+use bollard::image::CommitContainerOptions;
+use bollard::network::ConnectNetworkOptions;
+use bollard::Docker;
+use chrono::DateTime;
+use serde::Deserialize;
 use serde_json::json;
+use tracing::{info, warn};
 
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
+use crate::api::error::{ApiError, ApiErrorVariant};
+use crate::config::{BRANCH_LABEL, PROJECT_LABEL};
 use crate::SharedAppState;
 
-use super::CrudReturn;
+use super::container_spec::{
+    is_builtin_network, named_volumes, valid_name, ContainerSpec, MountSpec, UpdateSpec,
+};
+use super::image::{pull_image, valid_repository, valid_tag};
+use super::listing::{ListKind, ListParams, ListReturn, SortKey};
+use super::prune::{known_size, PruneFilters, PruneItem, PruneKind, PruneParams, PruneReport};
+use super::{checked_stream, docker_error, CrudReturn};
+
+/// Env vars with one of these in their name have their value hidden from non-admins
+const SENSITIVE_ENV: [&str; 8] = [
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "TOKEN",
+    "KEY",
+    "CREDENTIAL",
+    "AUTH",
+    "PRIVATE",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct TimeoutParams {
+    /// Seconds to wait for the container to stop before killing it, docker's default when not set
+    timeout: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KillParams {
+    /// A name like `SIGHUP` or a number, `SIGKILL` when not set
+    signal: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenameBody {
+    name: String,
+}
+
+/// Body of `POST /containers/:name/commit`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommitBody {
+    repo: String,
+    /// `latest` when not set
+    tag: Option<String>,
+    message: Option<String>,
+    /// The user committing when not set
+    author: Option<String>,
+    /// Pause the container while committing, on by default
+    pause: Option<bool>,
+    /// Dockerfile instructions applied to the image, such as `CMD ["app"]`
+    #[serde(default)]
+    changes: Vec<String>,
+}
 
-pub async fn containers(State(state): State<SharedAppState>) -> CrudReturn {
-    let ret = state
+#[derive(Debug, Deserialize)]
+pub struct InspectParams {
+    /// Include the size of the container filesystem, slow on large containers
+    #[serde(default)]
+    size: bool,
+}
+
+pub async fn containers(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<ListParams>,
+) -> ListReturn {
+    let filters = params.docker_filters(ListKind::Container)?;
+    let mut ret = state
         .docker
         .lock_owned()
         .await
         .list_containers(Some(ListContainersOptions::<String> {
             all: true,
+            // Computing sizes is slow, only do it when needed
+            size: params.sorts_by(SortKey::Size),
+            filters,
             ..Default::default()
         }))
         .await?;
+    ret.retain(|c| user.can_see(c.labels.as_ref()));
+    params.respond(ret, |c, key| match key {
+        SortKey::Created => c.created.into(),
+        SortKey::Size => c.size_rw.into(),
+        SortKey::Name => c.names.as_ref().and_then(|n| n.first().cloned()).into(),
+    })
+}
+
+pub async fn create_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Json(spec): Json<ContainerSpec>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    spec.validate().to_apierror(StatusCode::BAD_REQUEST)?;
+    if spec.needs_admin() {
+        user.require_admin()?;
+    }
+    // Without global access a container has to belong to a project the user may deploy
+    match spec.labels.get(PROJECT_LABEL) {
+        Some(project) => user.require_project(
+            project,
+            spec.labels.get(BRANCH_LABEL).map(String::as_str),
+            Access::Deploy,
+        )?,
+        None => user.require_global()?,
+    }
+
+    // Pulling can take a while, don't block other requests on the docker lock
+    let docker = state.docker.lock_owned().await.clone();
+    require_attachable(&user, &docker, &spec.mounts, &spec.networks).await?;
+    if spec.pull {
+        pull_image(&docker, &spec.image).await?;
+    }
+
+    let created = docker
+        .create_container(
+            spec.name.as_ref().map(|name| CreateContainerOptions {
+                name: name.clone(),
+                ..Default::default()
+            }),
+            spec.to_config(),
+        )
+        .await?;
+    info!(id = created.id, image = spec.image, "created container");
+
+    // Don't leave a half configured container behind
+    if let Err(e) = finish_create(&docker, &created.id, &spec).await {
+        if let Err(e) = docker
+            .remove_container(
+                &created.id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            warn!(?e, id = created.id, "could not remove failed container");
+        }
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "id": created.id,
+            "warnings": created.warnings,
+            "started": spec.start,
+        })),
+    ))
+}
+
+/// Named volumes and networks can hold another project's data, a container may only use those of its user.
+/// Host access through bind mounts and the `host` network is checked by `needs_admin`.
+pub async fn require_attachable(
+    user: &CurrentUser,
+    docker: &Docker,
+    mounts: &[MountSpec],
+    networks: &[String],
+) -> Result<(), ApiError> {
+    for volume in named_volumes(mounts) {
+        user.require_resource(docker, ResourceKind::Volume, volume, Access::Deploy)
+            .await?;
+    }
+    for network in networks.iter().filter(|n| !is_builtin_network(n)) {
+        user.require_resource(docker, ResourceKind::Network, network, Access::Deploy)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn finish_create(docker: &Docker, id: &str, spec: &ContainerSpec) -> anyhow::Result<()> {
+    for network in spec.networks.iter().skip(1) {
+        docker
+            .connect_network(
+                network,
+                ConnectNetworkOptions {
+                    container: id,
+                    ..Default::default()
+                },
+            )
+            .await?;
+    }
+    if spec.start {
+        docker
+            .start_container(id, None::<StartContainerOptions<String>>)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn inspect_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+    Query(params): Query<InspectParams>,
+) -> CrudReturn {
+    let docker = state.docker.lock_owned().await;
+    user.require_resource(&docker, ResourceKind::Container, &name, Access::View)
+        .await?;
+    let mut ret = docker
+        .inspect_container(&name, Some(InspectContainerOptions { size: params.size }))
+        .await?;
+
+    if !user.has_scope(Scope::Admin) {
+        if let Some(env) = ret.config.as_mut().and_then(|c| c.env.as_mut()) {
+            env.iter_mut().for_each(redact_env);
+        }
+    }
     Ok((StatusCode::OK, Json(json!(&ret))))
 }
 
+pub fn redact_env(var: &mut String) {
+    let Some((key, _)) = var.split_once('=') else {
+        return;
+    };
+    let upper = key.to_uppercase();
+    if SENSITIVE_ENV.iter().any(|s| upper.contains(s)) {
+        *var = format!("{key}=<redacted>");
+    }
+}
+
 pub async fn start_container(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path(name): Path<String>,
 ) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
     state
         .docker
         .lock_owned()
@@ -40,34 +274,349 @@ pub async fn start_container(
 
 pub async fn stop_container(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path(name): Path<String>,
+    Query(params): Query<TimeoutParams>,
 ) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
     state
         .docker
         .lock_owned()
         .await
-        .stop_container(&name, None::<StopContainerOptions>)
+        .stop_container(
+            &name,
+            params.timeout.map(|t| StopContainerOptions { t: t.into() }),
+        )
         .await?;
     Ok((StatusCode::OK, Json(json!({}))))
 }
 
 pub async fn restart_container(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path(name): Path<String>,
+    Query(params): Query<TimeoutParams>,
 ) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
     state
         .docker
         .lock_owned()
         .await
-        .restart_container(&name, None::<RestartContainerOptions>)
+        .restart_container(
+            &name,
+            params
+                .timeout
+                .map(|t| RestartContainerOptions { t: t as isize }),
+        )
         .await?;
     Ok((StatusCode::OK, Json(json!({}))))
 }
 
+pub async fn pause_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
+    state
+        .docker
+        .lock_owned()
+        .await
+        .pause_container(&name)
+        .await
+        .map_err(docker_error)?;
+    Ok((StatusCode::OK, Json(json!({}))))
+}
+
+pub async fn unpause_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
+    state
+        .docker
+        .lock_owned()
+        .await
+        .unpause_container(&name)
+        .await
+        .map_err(docker_error)?;
+    Ok((StatusCode::OK, Json(json!({}))))
+}
+
+pub async fn kill_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+    Query(params): Query<KillParams>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    let signal = params.signal.unwrap_or_else(|| "SIGKILL".to_owned());
+    if !valid_signal(&signal) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("invalid signal {signal:?}"),
+        ));
+    }
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
+    state
+        .docker
+        .lock_owned()
+        .await
+        .kill_container(&name, Some(KillContainerOptions { signal: &signal }))
+        .await
+        .map_err(docker_error)?;
+    info!(
+        container = name,
+        signal,
+        username = user.username,
+        "sent signal"
+    );
+    Ok((StatusCode::OK, Json(json!({}))))
+}
+
+/// Signal names (with or without `SIG`) or numbers, docker checks whether the name exists.
+fn valid_signal(signal: &str) -> bool {
+    if let Ok(number) = signal.parse::<u8>() {
+        return (1..=64).contains(&number);
+    }
+    let name = signal.strip_prefix("SIG").unwrap_or(signal);
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || matches!(c, '+' | '-'))
+}
+
+pub async fn rename_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+    Json(body): Json<RenameBody>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    if !valid_name(&body.name) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("invalid container name {:?}", body.name),
+        ));
+    }
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Manage,
+    )
+    .await?;
+    state
+        .docker
+        .lock_owned()
+        .await
+        .rename_container(&name, RenameContainerOptions { name: &body.name })
+        .await
+        .map_err(docker_error)?;
+    info!(
+        container = name,
+        new_name = body.name,
+        username = user.username,
+        "renamed container"
+    );
+    Ok((StatusCode::OK, Json(json!({ "name": body.name }))))
+}
+
+/// Files added, changed or deleted compared to the image.
+pub async fn container_changes(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+) -> CrudReturn {
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::View,
+    )
+    .await?;
+    let changes = state
+        .docker
+        .lock_owned()
+        .await
+        .container_changes(&name)
+        .await
+        .map_err(docker_error)?
+        .unwrap_or_default();
+    Ok((StatusCode::OK, Json(json!(changes))))
+}
+
+/// Snapshots a container into a new image.
+pub async fn commit_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+    Json(body): Json<CommitBody>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    let tag = body.tag.unwrap_or_else(|| "latest".to_owned());
+    if !valid_repository(&body.repo) || !valid_tag(&tag) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("invalid image reference {}:{tag}", body.repo),
+        ));
+    }
+    let docker = state.docker.lock_owned().await.clone();
+    user.require_resource(&docker, ResourceKind::Container, &name, Access::Deploy)
+        .await?;
+    // The image keeps the labels of the container, so it belongs to the same project
+    let labels = docker
+        .inspect_container(&name, None)
+        .await
+        .map_err(docker_error)?
+        .config
+        .and_then(|c| c.labels);
+    let image = format!("{}:{tag}", body.repo);
+    user.require_image_name(&docker, &image, labels.as_ref())
+        .await?;
+
+    let commit = docker
+        .commit_container(
+            CommitContainerOptions {
+                container: name.clone(),
+                repo: body.repo.clone(),
+                tag: tag.clone(),
+                comment: body.message.unwrap_or_default(),
+                author: body.author.unwrap_or_else(|| user.username.clone()),
+                pause: body.pause.unwrap_or(true),
+                changes: (!body.changes.is_empty()).then(|| body.changes.join("\n")),
+            },
+            Config::<String>::default(),
+        )
+        .await
+        .map_err(docker_error)?;
+    info!(
+        container = name,
+        image,
+        username = user.username,
+        "committed container"
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "image": image, "id": commit.id })),
+    ))
+}
+
+/// Streams the container filesystem as a tarball.
+pub async fn export_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+) -> Result<Response, ApiError> {
+    // The filesystem can hold secrets that are redacted everywhere else
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
+    let docker = state.docker.lock_owned().await.clone();
+    let export = checked_stream(docker.export_container(&name)).await?;
+    info!(
+        container = name,
+        username = user.username,
+        "exporting container"
+    );
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/x-tar".to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{name}.tar\""),
+            ),
+        ],
+        Body::from_stream(export),
+    )
+        .into_response())
+}
+
+/// Changes resource limits and the restart policy of a (running) container.
+pub async fn update_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+    Json(spec): Json<UpdateSpec>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    spec.validate().to_apierror(StatusCode::BAD_REQUEST)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Deploy,
+    )
+    .await?;
+    state
+        .docker
+        .lock_owned()
+        .await
+        .update_container(&name, spec.to_options())
+        .await
+        .map_err(docker_error)?;
+    info!(
+        container = name,
+        ?spec,
+        username = user.username,
+        "updated container"
+    );
+    Ok((StatusCode::OK, Json(json!({}))))
+}
+
 pub async fn remove_container(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path(name): Path<String>,
 ) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Manage,
+    )
+    .await?;
     state
         .docker
         .lock_owned()
@@ -82,3 +631,66 @@ pub async fn remove_container(
         .await?;
     Ok((StatusCode::OK, Json(json!({}))))
 }
+
+pub async fn prune_containers(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<PruneParams>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_global()?;
+    let filters = params.filters(PruneKind::Container)?;
+    let docker = state.docker.lock_owned().await;
+
+    let plan = plan_container_prune(&docker, &filters).await?;
+    if params.dry_run {
+        return Ok((StatusCode::OK, Json(json!(PruneReport::planned(plan)))));
+    }
+
+    let ret = docker
+        .prune_containers(Some(PruneContainersOptions {
+            filters: filters.docker_filters(PruneKind::Container),
+        }))
+        .await?;
+    let report = PruneReport::executed(
+        plan,
+        &ret.containers_deleted.unwrap_or_default(),
+        ret.space_reclaimed.and_then(known_size),
+    );
+    Ok((StatusCode::OK, Json(json!(report))))
+}
+
+/// Same selection as the daemon: every container that is not running.
+async fn plan_container_prune(
+    docker: &Docker,
+    filters: &PruneFilters,
+) -> anyhow::Result<Vec<PruneItem>> {
+    let stopped = docker
+        .list_containers(Some(ListContainersOptions::<String> {
+            all: true,
+            size: true,
+            filters: HashMap::from([(
+                "status".to_owned(),
+                vec!["created".to_owned(), "exited".to_owned(), "dead".to_owned()],
+            )]),
+            ..Default::default()
+        }))
+        .await?;
+
+    Ok(stopped
+        .into_iter()
+        .filter(|c| filters.matches_labels(c.labels.as_ref()))
+        .filter(|c| {
+            filters.created_before_until(c.created.and_then(|t| DateTime::from_timestamp(t, 0)))
+        })
+        .filter_map(|c| {
+            Some(PruneItem {
+                name: c
+                    .names
+                    .and_then(|n| n.first().map(|n| n.trim_start_matches('/').to_owned())),
+                size: c.size_rw.and_then(known_size),
+                id: c.id?,
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use bollard::{
+    container::{Config, UpdateContainerOptions},
+    models::{HostConfig, Mount, MountTypeEnum, PortBinding, RestartPolicy, RestartPolicyNameEnum},
+};
+use serde::Deserialize;
+
+/// Docker refuses memory limits below this
+const MIN_MEMORY: i64 = 6 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortSpec {
+    pub container: u16,
+    /// Random host port when not set
+    pub host: Option<u16>,
+    pub host_ip: Option<String>,
+    #[serde(default)]
+    pub protocol: Protocol,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountKind {
+    #[default]
+    Volume,
+    Bind,
+    Tmpfs,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountSpec {
+    /// Volume name or host path, unused for tmpfs
+    #[serde(default)]
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub kind: MountKind,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartMode {
+    #[default]
+    No,
+    Always,
+    UnlessStopped,
+    OnFailure,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestartSpec {
+    #[serde(default)]
+    pub mode: RestartMode,
+    /// Only for `on-failure`
+    pub max_retries: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceSpec {
+    /// Bytes
+    pub memory: Option<i64>,
+    /// Fractional cpus, e.g. 0.5
+    pub cpus: Option<f64>,
+    pub pids_limit: Option<i64>,
+}
+
+/// Body of `POST /containers`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerSpec {
+    pub image: String,
+    pub name: Option<String>,
+    pub cmd: Option<Vec<String>>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub mounts: Vec<MountSpec>,
+    /// The first one is used on creation, the container is connected to the others afterwards
+    #[serde(default)]
+    pub networks: Vec<String>,
+    #[serde(default)]
+    pub restart: RestartSpec,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub resources: ResourceSpec,
+    /// Pull the image before creating the container
+    #[serde(default)]
+    pub pull: bool,
+    /// Start the container once it is created
+    #[serde(default)]
+    pub start: bool,
+}
+
+impl ContainerSpec {
+    pub fn validate(&self) -> Result<()> {
+        if self.image.trim().is_empty() || self.image.chars().any(char::is_whitespace) {
+            bail!("invalid image {:?}", self.image);
+        }
+        if let Some(name) = &self.name {
+            if !valid_name(name) {
+                bail!("invalid container name {name:?}");
+            }
+        }
+        if let Some(key) = self.env.keys().find(|k| k.is_empty() || k.contains('=')) {
+            bail!("invalid env var name {key:?}");
+        }
+        validate_ports(&self.ports)?;
+        validate_mounts(&self.mounts)?;
+        if self.networks.iter().any(|n| n.is_empty()) {
+            bail!("network name can not be empty");
+        }
+        self.restart.validate()?;
+        self.resources.validate()
+    }
+
+    /// Settings that give access to the host, only admins may use them.
+    pub fn needs_admin(&self) -> bool {
+        self.mounts.iter().any(|m| m.kind == MountKind::Bind)
+            || self
+                .networks
+                .iter()
+                .any(|n| n == "host" || n.starts_with("container:"))
+    }
+
+    pub fn to_config(&self) -> Config<String> {
+        Config {
+            image: Some(self.image.clone()),
+            cmd: self.cmd.clone(),
+            env: Some(self.env.iter().map(|(k, v)| format!("{k}={v}")).collect()),
+            labels: Some(self.labels.clone()),
+            exposed_ports: Some(exposed_ports(&self.ports)),
+            host_config: Some(HostConfig {
+                port_bindings: Some(port_bindings(&self.ports)),
+                mounts: Some(self.mounts.iter().map(MountSpec::to_mount).collect()),
+                restart_policy: Some(self.restart.to_policy()),
+                network_mode: self.networks.first().cloned(),
+                memory: self.resources.memory,
+                nano_cpus: self.resources.nano_cpus(),
+                pids_limit: self.resources.pids_limit,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+impl PortSpec {
+    /// `80/tcp`, how docker keys ports
+    fn key(&self) -> String {
+        let protocol = match self.protocol {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Sctp => "sctp",
+        };
+        format!("{}/{protocol}", self.container)
+    }
+}
+
+pub fn validate_ports(ports: &[PortSpec]) -> Result<()> {
+    if ports.iter().any(|p| p.container == 0) {
+        bail!("container port can not be 0");
+    }
+    Ok(())
+}
+
+pub fn exposed_ports(ports: &[PortSpec]) -> HashMap<String, HashMap<(), ()>> {
+    ports.iter().map(|p| (p.key(), HashMap::new())).collect()
+}
+
+pub fn port_bindings(ports: &[PortSpec]) -> HashMap<String, Option<Vec<PortBinding>>> {
+    let mut bindings: HashMap<String, Option<Vec<PortBinding>>> = HashMap::new();
+    for port in ports {
+        bindings
+            .entry(port.key())
+            .or_default()
+            .get_or_insert_with(Vec::new)
+            .push(PortBinding {
+                host_ip: port.host_ip.clone(),
+                host_port: port.host.map(|h| h.to_string()),
+            });
+    }
+    bindings
+}
+
+impl MountSpec {
+    pub fn to_mount(&self) -> Mount {
+        Mount {
+            target: Some(self.target.clone()),
+            source: (self.kind != MountKind::Tmpfs && !self.source.is_empty())
+                .then(|| self.source.clone()),
+            typ: Some(match self.kind {
+                MountKind::Volume => MountTypeEnum::VOLUME,
+                MountKind::Bind => MountTypeEnum::BIND,
+                MountKind::Tmpfs => MountTypeEnum::TMPFS,
+            }),
+            read_only: Some(self.read_only),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn validate_mounts(mounts: &[MountSpec]) -> Result<()> {
+    for mount in mounts {
+        if !mount.target.starts_with('/') {
+            bail!("mount target {:?} must be an absolute path", mount.target);
+        }
+        match mount.kind {
+            MountKind::Bind if !mount.source.starts_with('/') => {
+                bail!(
+                    "bind mount source {:?} must be an absolute path",
+                    mount.source
+                )
+            }
+            MountKind::Volume if !mount.source.is_empty() && !valid_name(&mount.source) => {
+                bail!("invalid volume name {:?}", mount.source)
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Volumes mounted by name, they are created on first use when they don't exist.
+pub fn named_volumes(mounts: &[MountSpec]) -> impl Iterator<Item = &str> {
+    mounts
+        .iter()
+        .filter(|m| m.kind == MountKind::Volume && !m.source.is_empty())
+        .map(|m| m.source.as_str())
+}
+
+/// Networks docker creates itself, they belong to no project
+pub fn is_builtin_network(network: &str) -> bool {
+    matches!(network, "bridge" | "default" | "none")
+}
+
+impl RestartSpec {
+    fn validate(&self) -> Result<()> {
+        if self.max_retries.is_some() && self.mode != RestartMode::OnFailure {
+            bail!("max_retries only applies to the on-failure restart mode");
+        }
+        Ok(())
+    }
+
+    fn to_policy(&self) -> RestartPolicy {
+        RestartPolicy {
+            name: Some(match self.mode {
+                RestartMode::No => RestartPolicyNameEnum::NO,
+                RestartMode::Always => RestartPolicyNameEnum::ALWAYS,
+                RestartMode::UnlessStopped => RestartPolicyNameEnum::UNLESS_STOPPED,
+                RestartMode::OnFailure => RestartPolicyNameEnum::ON_FAILURE,
+            }),
+            maximum_retry_count: self.max_retries,
+        }
+    }
+}
+
+impl ResourceSpec {
+    fn validate(&self) -> Result<()> {
+        if self.memory.is_some_and(|m| m < MIN_MEMORY) {
+            bail!("memory limit must be at least {MIN_MEMORY} bytes");
+        }
+        if self.cpus.is_some_and(|c| !c.is_finite() || c <= 0.0) {
+            bail!("cpus must be positive");
+        }
+        Ok(())
+    }
+
+    fn nano_cpus(&self) -> Option<i64> {
+        self.cpus.map(|c| (c * 1e9) as i64)
+    }
+}
+
+/// Body of `POST /containers/:name/update`, only the given settings change.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateSpec {
+    #[serde(default)]
+    pub resources: ResourceSpec,
+    pub restart: Option<RestartSpec>,
+}
+
+impl UpdateSpec {
+    pub fn validate(&self) -> Result<()> {
+        let r = &self.resources;
+        if r.memory.is_none()
+            && r.cpus.is_none()
+            && r.pids_limit.is_none()
+            && self.restart.is_none()
+        {
+            bail!("nothing to update");
+        }
+        if let Some(restart) = &self.restart {
+            restart.validate()?;
+        }
+        self.resources.validate()
+    }
+
+    pub fn to_options(&self) -> UpdateContainerOptions<String> {
+        UpdateContainerOptions {
+            memory: self.resources.memory,
+            nano_cp_us: self.resources.nano_cpus(),
+            pids_limit: self.resources.pids_limit,
+            restart_policy: self.restart.as_ref().map(RestartSpec::to_policy),
+            ..Default::default()
+        }
+    }
+}
+
+/// Docker's rule for container and volume names
+pub fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
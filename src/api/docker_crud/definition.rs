@@ -0,0 +1,654 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::extract::Query;
+use bollard::{
+    container::ListContainersOptions,
+    models::{ContainerConfig, ContainerInspectResponse, MountPointTypeEnum},
+    Docker,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
+use crate::api::error::{ApiError, ApiErrorVariant};
+use crate::SharedAppState;
+
+use super::container::redact_env;
+use super::{docker_error, CrudReturn};
+
+#[derive(Debug, Deserialize)]
+pub struct GroupParams {
+    /// `key` or `key=value`, can be repeated
+    #[serde(default)]
+    label: Vec<String>,
+}
+
+/// A compose service, also the source of the `docker run` command. Empty fields are docker's defaults.
+#[derive(Debug, Default, Serialize)]
+struct Service {
+    container_name: String,
+    image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    working_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entrypoint: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    environment: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ports: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    volumes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tmpfs: Vec<String>,
+    /// `host`, `none` or `container:<id>`, user networks go in `networks`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    network_mode: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    networks: BTreeMap<String, ServiceNetwork>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restart: Option<String>,
+    /// Bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mem_limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpus: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pids_limit: Option<i64>,
+    #[serde(skip_serializing_if = "is_false")]
+    privileged: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    init: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    read_only: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    tty: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    stdin_open: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cap_add: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cap_drop: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extra_hosts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_signal: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ServiceNetwork {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    aliases: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv4_address: Option<String>,
+}
+
+/// Volumes and networks already exist on the host, so the compose file refers to them as external.
+#[derive(Debug, Serialize)]
+struct External {
+    external: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ComposeFile {
+    services: BTreeMap<String, Service>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    volumes: BTreeMap<String, External>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    networks: BTreeMap<String, External>,
+}
+
+impl Service {
+    /// Compose replaces `$VAR` and `${VAR}` in values, `$$` keeps a literal `$`.
+    fn escaped_for_compose(mut self) -> Self {
+        let escape = |v: &mut String| *v = v.replace('$', "$$");
+        self.environment.values_mut().for_each(escape);
+        self.labels.values_mut().for_each(escape);
+        self.entrypoint.iter_mut().flatten().for_each(escape);
+        self.command.iter_mut().flatten().for_each(escape);
+        self.working_dir.iter_mut().for_each(escape);
+        self.user.iter_mut().for_each(escape);
+        self.volumes.iter_mut().for_each(escape);
+        self.tmpfs.iter_mut().for_each(escape);
+        self
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// `GET /containers/:name/definition`
+pub async fn container_definition(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+) -> CrudReturn {
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::View,
+    )
+    .await?;
+
+    let docker = state.docker.lock_owned().await.clone();
+    let container = docker
+        .inspect_container(&name, None)
+        .await
+        .map_err(docker_error)?;
+    definitions(&docker, &user, vec![container]).await
+}
+
+/// `GET /containers/definition?label=..`, all containers with the labels the user can see.
+pub async fn group_definition(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<GroupParams>,
+) -> CrudReturn {
+    if params.label.is_empty()
+        || params
+            .label
+            .iter()
+            .any(|l| l.is_empty() || l.starts_with('='))
+    {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("select containers with one or more label=key[=value]"),
+        ));
+    }
+
+    let docker = state.docker.lock_owned().await.clone();
+    let listed = docker
+        .list_containers(Some(ListContainersOptions::<String> {
+            all: true,
+            filters: HashMap::from([("label".to_owned(), params.label)]),
+            ..Default::default()
+        }))
+        .await?;
+    let mut containers = Vec::new();
+    for id in listed
+        .into_iter()
+        .filter(|c| user.can_see(c.labels.as_ref()))
+        .filter_map(|c| c.id)
+    {
+        containers.push(
+            docker
+                .inspect_container(&id, None)
+                .await
+                .map_err(docker_error)?,
+        );
+    }
+    if containers.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("no containers with these labels"),
+        ));
+    }
+    definitions(&docker, &user, containers).await
+}
+
+async fn definitions(
+    docker: &Docker,
+    user: &CurrentUser,
+    containers: Vec<ContainerInspectResponse>,
+) -> CrudReturn {
+    let mut compose = ComposeFile::default();
+    let mut run = Vec::new();
+    for mut container in containers {
+        if !user.has_scope(Scope::Admin) {
+            if let Some(env) = container.config.as_mut().and_then(|c| c.env.as_mut()) {
+                env.iter_mut().for_each(redact_env);
+            }
+        }
+        let image = container
+            .config
+            .as_ref()
+            .and_then(|c| c.image.as_deref())
+            .unwrap_or_default();
+        // Whatever the image sets is a default, an image that is gone only loses that comparison
+        let image_config = docker
+            .inspect_image(image)
+            .await
+            .ok()
+            .and_then(|i| i.config)
+            .unwrap_or_default();
+
+        let service = to_service(&container, &image_config);
+        run.extend(run_commands(&service));
+        for volume in service.volumes.iter().filter_map(|v| named_volume(v)) {
+            compose
+                .volumes
+                .insert(volume.to_owned(), External { external: true });
+        }
+        for network in service.networks.keys() {
+            compose
+                .networks
+                .insert(network.clone(), External { external: true });
+        }
+        compose.services.insert(
+            service.container_name.clone(),
+            service.escaped_for_compose(),
+        );
+    }
+
+    let yaml = serde_yaml::to_string(&compose).to_apierror(StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "containers": compose.services.keys().collect::<Vec<_>>(),
+            "run": run.join("\n"),
+            "compose": yaml,
+        })),
+    ))
+}
+
+fn to_service(container: &ContainerInspectResponse, image: &ContainerConfig) -> Service {
+    let id = container.id.as_deref().unwrap_or_default();
+    let name = container
+        .name
+        .as_deref()
+        .unwrap_or(id)
+        .trim_start_matches('/')
+        .to_owned();
+    let config = container.config.clone().unwrap_or_default();
+    let host = container.host_config.clone().unwrap_or_default();
+    let differs = |value: &Option<String>, default: &Option<String>| {
+        value
+            .as_ref()
+            .filter(|v| !v.is_empty() && Some(*v) != default.as_ref())
+            .cloned()
+    };
+    let image_env = image.env.clone().unwrap_or_default();
+    let image_labels = image.labels.clone().unwrap_or_default();
+    let entrypoint = config
+        .entrypoint
+        .clone()
+        .filter(|e| config.entrypoint != image.entrypoint && !e.is_empty());
+
+    let mut service = Service {
+        container_name: name.clone(),
+        image: config.image.clone().unwrap_or_default(),
+        // Docker uses the short id when no hostname is given
+        hostname: differs(&config.hostname, &None).filter(|h| !id.starts_with(h.as_str())),
+        user: differs(&config.user, &image.user),
+        working_dir: differs(&config.working_dir, &image.working_dir),
+        // Setting the entrypoint clears the command of the image, so it is kept even when it is the image's
+        command: config
+            .cmd
+            .clone()
+            .filter(|c| (entrypoint.is_some() || config.cmd != image.cmd) && !c.is_empty()),
+        entrypoint,
+        environment: config
+            .env
+            .iter()
+            .flatten()
+            .filter(|e| !image_env.contains(e))
+            .map(|e| {
+                let (key, value) = e.split_once('=').unwrap_or((e, ""));
+                (key.to_owned(), value.to_owned())
+            })
+            .collect(),
+        labels: config
+            .labels
+            .iter()
+            .flatten()
+            .filter(|(k, v)| image_labels.get(*k) != Some(v))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        mem_limit: host.memory.filter(|m| *m > 0),
+        cpus: host.nano_cpus.filter(|c| *c > 0).map(|c| c as f64 / 1e9),
+        pids_limit: host.pids_limit.filter(|p| *p > 0),
+        privileged: host.privileged.unwrap_or(false),
+        init: host.init.unwrap_or(false),
+        read_only: host.readonly_rootfs.unwrap_or(false),
+        tty: config.tty.unwrap_or(false),
+        stdin_open: config.open_stdin.unwrap_or(false),
+        cap_add: host.cap_add.clone().unwrap_or_default(),
+        cap_drop: host.cap_drop.clone().unwrap_or_default(),
+        extra_hosts: host.extra_hosts.clone().unwrap_or_default(),
+        stop_signal: differs(&config.stop_signal, &image.stop_signal),
+        ..Default::default()
+    };
+
+    let mut ports: Vec<_> = host.port_bindings.iter().flatten().collect();
+    ports.sort_by(|a, b| a.0.cmp(b.0));
+    for (port, bindings) in ports {
+        let port = port.strip_suffix("/tcp").unwrap_or(port);
+        for binding in bindings.iter().flatten() {
+            let host_port = binding.host_port.as_deref().unwrap_or_default();
+            service.ports.push(match binding.host_ip.as_deref() {
+                Some(ip) if !ip.is_empty() && host_port.is_empty() => format!("{ip}::{port}"),
+                Some(ip) if !ip.is_empty() => format!("{ip}:{host_port}:{port}"),
+                _ if host_port.is_empty() => port.to_owned(),
+                _ => format!("{host_port}:{port}"),
+            });
+        }
+    }
+
+    let image_volumes = image.volumes.clone().unwrap_or_default();
+    for mount in container.mounts.iter().flatten() {
+        let target = mount.destination.clone().unwrap_or_default();
+        let suffix = if mount.rw == Some(false) { ":ro" } else { "" };
+        match mount.typ {
+            Some(MountPointTypeEnum::BIND) => {
+                let source = mount.source.as_deref().unwrap_or_default();
+                service.volumes.push(format!("{source}:{target}{suffix}"));
+            }
+            Some(MountPointTypeEnum::VOLUME) => match mount.name.as_deref() {
+                Some(volume) if !is_anonymous(volume) => {
+                    service.volumes.push(format!("{volume}:{target}{suffix}"));
+                }
+                // Volumes the image declares are created anyway
+                _ if image_volumes.contains_key(&target) => {}
+                _ => service.volumes.push(target),
+            },
+            Some(MountPointTypeEnum::TMPFS) => service.tmpfs.push(target),
+            _ => {}
+        }
+    }
+    for (target, options) in host.tmpfs.iter().flatten() {
+        if !service.tmpfs.contains(target) {
+            service.tmpfs.push(if options.is_empty() {
+                target.clone()
+            } else {
+                format!("{target}:{options}")
+            });
+        }
+    }
+
+    match host.network_mode.as_deref() {
+        Some(mode @ ("host" | "none")) => service.network_mode = Some(mode.to_owned()),
+        Some(mode) if mode.starts_with("container:") => {
+            service.network_mode = Some(mode.to_owned())
+        }
+        _ => {
+            let networks = container
+                .network_settings
+                .as_ref()
+                .and_then(|n| n.networks.clone())
+                .unwrap_or_default();
+            for (network, settings) in networks.into_iter().filter(|(n, _)| n != "bridge") {
+                let aliases = settings
+                    .aliases
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|a| !id.starts_with(a.as_str()) && *a != name)
+                    .collect();
+                let ipv4_address = settings
+                    .ipam_config
+                    .and_then(|c| c.ipv4_address)
+                    .filter(|a| !a.is_empty());
+                service.networks.insert(
+                    network,
+                    ServiceNetwork {
+                        aliases,
+                        ipv4_address,
+                    },
+                );
+            }
+        }
+    }
+
+    service.restart = host.restart_policy.and_then(|p| {
+        let name = p.name.map(|n| n.to_string()).unwrap_or_default();
+        match name.as_str() {
+            "" | "no" => None,
+            "on-failure" => match p.maximum_retry_count {
+                Some(n) if n > 0 => Some(format!("on-failure:{n}")),
+                _ => Some(name),
+            },
+            _ => Some(name),
+        }
+    });
+
+    service
+}
+
+/// Docker names anonymous volumes with 64 hex characters.
+fn is_anonymous(volume: &str) -> bool {
+    volume.len() == 64 && volume.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn named_volume(volume: &str) -> Option<&str> {
+    volume
+        .split_once(':')
+        .map(|(source, _)| source)
+        .filter(|s| !s.starts_with('/') && !s.starts_with('.'))
+}
+
+/// `docker run` for the service, followed by `docker network connect` for any network after the first.
+fn run_commands(service: &Service) -> Vec<String> {
+    let mut args: Vec<String> = vec!["docker".into(), "run".into(), "-d".into()];
+    let mut flag = |name: &str, value: &str| {
+        args.push(name.to_owned());
+        args.push(value.to_owned());
+    };
+
+    flag("--name", &service.container_name);
+    if let Some(hostname) = &service.hostname {
+        flag("--hostname", hostname);
+    }
+    if let Some(user) = &service.user {
+        flag("--user", user);
+    }
+    if let Some(workdir) = &service.working_dir {
+        flag("--workdir", workdir);
+    }
+    // Only the executable goes into --entrypoint, its arguments come before the command
+    let (entrypoint, entrypoint_args) = match service.entrypoint.as_deref() {
+        Some([first, rest @ ..]) => (Some(first), rest),
+        _ => (None, &[][..]),
+    };
+    if let Some(entrypoint) = entrypoint {
+        flag("--entrypoint", entrypoint);
+    }
+    for (key, value) in &service.environment {
+        flag("-e", &format!("{key}={value}"));
+    }
+    for (key, value) in &service.labels {
+        flag("--label", &format!("{key}={value}"));
+    }
+    for port in &service.ports {
+        flag("-p", port);
+    }
+    for volume in &service.volumes {
+        flag("-v", volume);
+    }
+    for tmpfs in &service.tmpfs {
+        flag("--tmpfs", tmpfs);
+    }
+    if let Some(mode) = &service.network_mode {
+        flag("--network", mode);
+    }
+    let mut networks = service.networks.iter();
+    if let Some((network, settings)) = networks.next() {
+        flag("--network", network);
+        for alias in &settings.aliases {
+            flag("--network-alias", alias);
+        }
+        if let Some(ip) = &settings.ipv4_address {
+            flag("--ip", ip);
+        }
+    }
+    if let Some(restart) = &service.restart {
+        flag("--restart", restart);
+    }
+    if let Some(memory) = service.mem_limit {
+        flag("--memory", &memory.to_string());
+    }
+    if let Some(cpus) = service.cpus {
+        flag("--cpus", &cpus.to_string());
+    }
+    if let Some(pids) = service.pids_limit {
+        flag("--pids-limit", &pids.to_string());
+    }
+    for cap in &service.cap_add {
+        flag("--cap-add", cap);
+    }
+    for cap in &service.cap_drop {
+        flag("--cap-drop", cap);
+    }
+    for host in &service.extra_hosts {
+        flag("--add-host", host);
+    }
+    if let Some(signal) = &service.stop_signal {
+        flag("--stop-signal", signal);
+    }
+    for (set, switch) in [
+        (service.privileged, "--privileged"),
+        (service.init, "--init"),
+        (service.read_only, "--read-only"),
+        (service.tty, "-t"),
+        (service.stdin_open, "-i"),
+    ] {
+        if set {
+            args.push(switch.to_owned());
+        }
+    }
+    args.push(service.image.clone());
+    args.extend(entrypoint_args.iter().cloned());
+    args.extend(service.command.iter().flatten().cloned());
+
+    let mut commands = vec![shell_join(&args)];
+    for (network, settings) in networks {
+        let mut args = vec!["docker".to_owned(), "network".into(), "connect".into()];
+        for alias in &settings.aliases {
+            args.extend(["--alias".to_owned(), alias.clone()]);
+        }
+        if let Some(ip) = &settings.ipv4_address {
+            args.extend(["--ip".to_owned(), ip.clone()]);
+        }
+        args.extend([network.clone(), service.container_name.clone()]);
+        commands.push(shell_join(&args));
+    }
+    commands
+}
+
+fn shell_join(args: &[String]) -> String {
+    args.iter()
+        .map(|a| shell_quote(a))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if safe {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn postgres_image() -> ContainerConfig {
+        ContainerConfig {
+            image: Some("postgres:16".to_owned()),
+            entrypoint: Some(vec!["docker-entrypoint.sh".to_owned()]),
+            cmd: Some(vec!["postgres".to_owned()]),
+            env: Some(vec!["PGDATA=/var/lib/postgresql/data".to_owned()]),
+            ..Default::default()
+        }
+    }
+
+    fn container(config: ContainerConfig) -> ContainerInspectResponse {
+        ContainerInspectResponse {
+            id: Some("4f1c2d3e".to_owned()),
+            name: Some("/db".to_owned()),
+            config: Some(config),
+            ..Default::default()
+        }
+    }
+
+    fn strings(values: &[&str]) -> Option<Vec<String>> {
+        Some(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    #[test]
+    fn image_defaults_are_left_out() {
+        let mut config = postgres_image();
+        config
+            .env
+            .get_or_insert_with(Vec::new)
+            .push("POSTGRES_PASSWORD=secret".to_owned());
+        let service = to_service(&container(config), &postgres_image());
+
+        assert_eq!(service.entrypoint, None);
+        assert_eq!(service.command, None);
+        assert_eq!(
+            service.environment,
+            BTreeMap::from([("POSTGRES_PASSWORD".to_owned(), "secret".to_owned())])
+        );
+        assert_eq!(
+            run_commands(&service),
+            ["docker run -d --name db -e POSTGRES_PASSWORD=secret postgres:16"]
+        );
+    }
+
+    #[test]
+    fn entrypoint_override_keeps_the_command() {
+        let config = ContainerConfig {
+            entrypoint: strings(&["/bin/sh", "-c"]),
+            ..postgres_image()
+        };
+        let service = to_service(&container(config), &postgres_image());
+
+        assert_eq!(service.entrypoint, strings(&["/bin/sh", "-c"]));
+        assert_eq!(service.command, strings(&["postgres"]));
+        assert_eq!(
+            run_commands(&service),
+            ["docker run -d --name db --entrypoint /bin/sh postgres:16 -c postgres"]
+        );
+    }
+
+    #[test]
+    fn command_override_round_trips() {
+        let config = ContainerConfig {
+            cmd: strings(&["postgres", "-c", "max_connections=200"]),
+            ..postgres_image()
+        };
+        let service = to_service(&container(config.clone()), &postgres_image());
+        assert_eq!(service.entrypoint, None);
+        assert_eq!(service.command, config.cmd);
+
+        // What the run command passes after the image is the command the container had
+        let run = run_commands(&service).remove(0);
+        let (_, args) = run.split_once("postgres:16 ").unwrap();
+        assert_eq!(args, "postgres -c max_connections=200");
+    }
+
+    #[test]
+    fn only_compose_escapes_dollars() {
+        let mut config = postgres_image();
+        config.env = Some(vec!["GREETING=$HOME costs $5".to_owned()]);
+        let service = to_service(&container(config), &postgres_image());
+
+        assert_eq!(
+            run_commands(&service),
+            ["docker run -d --name db -e 'GREETING=$HOME costs $5' postgres:16"]
+        );
+        let escaped = service.escaped_for_compose();
+        assert_eq!(escaped.environment["GREETING"], "$$HOME costs $$5");
+    }
+}
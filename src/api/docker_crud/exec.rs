@@ -0,0 +1,319 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, bail};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use bollard::{
+    container::LogOutput,
+    exec::{CreateExecOptions, StartExecOptions, StartExecResults},
+    Docker,
+};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
+use crate::api::error::ApiErrorVariant;
+use crate::api::projects::executor::{IoEvent, ProjectIoHandle};
+use crate::api::projects::BaseProject;
+use crate::SharedAppState;
+
+use super::CrudReturn;
+
+/// Seconds
+const DEFAULT_EXEC_TIMEOUT: u64 = 60;
+const MAX_EXEC_TIMEOUT: u64 = 60 * 60;
+/// Status of tracked jobs that ran out of time, like coreutils' `timeout`
+const TIMED_OUT_STATUS: usize = 124;
+/// Bytes of stdout and stderr together that are returned by an untracked exec
+const MAX_EXEC_OUTPUT: usize = 1024 * 1024;
+/// Output without a newline is passed on in pieces of this many bytes
+const MAX_LINE: usize = 64 * 1024;
+
+/// Body of `POST /containers/:id/exec`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecSpec {
+    cmd: Vec<String>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    user: Option<String>,
+    workdir: Option<String>,
+    /// Seconds, 60 when not set
+    timeout: Option<u64>,
+    /// Run as a job of the io executor instead of waiting for the output
+    #[serde(default)]
+    track: bool,
+}
+
+impl ExecSpec {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.cmd.first().is_none_or(|c| c.trim().is_empty()) {
+            bail!("cmd can not be empty");
+        }
+        if let Some(key) = self.env.keys().find(|k| k.is_empty() || k.contains('=')) {
+            bail!("invalid env var name {key:?}");
+        }
+        if self.timeout.is_some_and(|t| t == 0 || t > MAX_EXEC_TIMEOUT) {
+            bail!("timeout must be between 1 and {MAX_EXEC_TIMEOUT} seconds");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExecResult {
+    /// Not set when the command timed out
+    exit_code: Option<i64>,
+    stdout: String,
+    stderr: String,
+    timed_out: bool,
+    /// Output past `MAX_EXEC_OUTPUT` was dropped
+    truncated: bool,
+}
+
+/// Output of an untracked exec, lines past the limit are dropped as they come in.
+#[derive(Default)]
+struct CollectedOutput {
+    stdout: String,
+    stderr: String,
+    truncated: bool,
+}
+
+impl CollectedOutput {
+    fn push(&mut self, event: IoEvent) {
+        let used = self.stdout.len() + self.stderr.len();
+        let (total, line) = match event {
+            IoEvent::Stdout(l) => (&mut self.stdout, l),
+            IoEvent::Stderr(l) => (&mut self.stderr, l),
+            IoEvent::Exit(_) => return,
+        };
+        if used + line.len() + 1 > MAX_EXEC_OUTPUT {
+            self.truncated = true;
+            return;
+        }
+        total.push_str(&line);
+        total.push('\n');
+    }
+}
+
+/// Runs a command in a container without a terminal.
+/// Docker can not kill an exec, so a command that times out may keep running in the container.
+pub async fn exec_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(id): Path<String>,
+    Json(spec): Json<ExecSpec>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    spec.validate().to_apierror(StatusCode::BAD_REQUEST)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &id,
+        Access::Deploy,
+    )
+    .await?;
+
+    let docker = state.docker.lock_owned().await.clone();
+    let exec = docker
+        .create_exec(
+            &id,
+            CreateExecOptions {
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                tty: Some(false),
+                env: Some(spec.env.iter().map(|(k, v)| format!("{k}={v}")).collect()),
+                cmd: Some(spec.cmd.clone()),
+                user: spec.user,
+                working_dir: spec.workdir,
+                ..Default::default()
+            },
+        )
+        .await?;
+    info!(
+        container = id,
+        exec = exec.id,
+        cmd = ?spec.cmd,
+        tracked = spec.track,
+        username = user.username,
+        "running command"
+    );
+
+    let timeout = Duration::from_secs(spec.timeout.unwrap_or(DEFAULT_EXEC_TIMEOUT));
+    let (tx, rx) = mpsc::unbounded();
+
+    if spec.track {
+        let labels = docker
+            .inspect_container(&id, None)
+            .await?
+            .config
+            .and_then(|c| c.labels);
+        let project = BaseProject::from_labels(labels.as_ref(), &format!("container:{id}"));
+
+        tokio::spawn(async move {
+            let status = match tokio::time::timeout(timeout, run_exec(&docker, &exec.id, &tx)).await
+            {
+                // Negative codes are no valid status, they count as a failure
+                Ok(Ok(code)) => code.and_then(|c| usize::try_from(c).ok()).unwrap_or(1),
+                Ok(Err(error)) => {
+                    let _ = tx.unbounded_send(IoEvent::Stderr(format!("exec failed: {error}")));
+                    1
+                }
+                Err(_) => {
+                    let _ = tx.unbounded_send(IoEvent::Stderr(format!(
+                        "timed out after {}s",
+                        timeout.as_secs()
+                    )));
+                    TIMED_OUT_STATUS
+                }
+            };
+            let _ = tx.unbounded_send(IoEvent::Exit(status));
+        });
+
+        let handle = ProjectIoHandle::from_stream(project.clone(), Box::pin(rx))
+            .with_tag(format!("exec {id}"));
+        let io_id = state
+            .io_executor
+            .exec(handle)
+            .await
+            .to_apierror(StatusCode::INTERNAL_SERVER_ERROR)?;
+        return Ok((
+            StatusCode::OK,
+            Json(json!({ "project": project, "io_id": io_id })),
+        ));
+    }
+
+    // The output is collected while the command runs, so it never piles up in the channel
+    let run = async {
+        let result = tokio::time::timeout(timeout, run_exec(&docker, &exec.id, &tx)).await;
+        drop(tx);
+        result
+    };
+    let collect = rx.fold(CollectedOutput::default(), |mut output, event| async move {
+        output.push(event);
+        output
+    });
+    let (result, output) = tokio::join!(run, collect);
+
+    let (exit_code, timed_out) = match result {
+        Ok(code) => (code?, false),
+        Err(_) => {
+            warn!(container = id, exec = exec.id, "command timed out");
+            (None, true)
+        }
+    };
+    Ok((
+        StatusCode::OK,
+        Json(json!(ExecResult {
+            exit_code,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            timed_out,
+            truncated: output.truncated,
+        })),
+    ))
+}
+
+/// Runs a command for servcur itself and returns its exit code and stdout.
+pub async fn exec_output(
+    docker: &Docker,
+    container: &str,
+    cmd: Vec<String>,
+    user: Option<String>,
+    timeout: Duration,
+) -> anyhow::Result<(Option<i64>, Vec<String>)> {
+    let exec = docker
+        .create_exec(
+            container,
+            CreateExecOptions {
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                tty: Some(false),
+                cmd: Some(cmd),
+                user,
+                ..Default::default()
+            },
+        )
+        .await?;
+    let (tx, rx) = mpsc::unbounded();
+    let code = tokio::time::timeout(timeout, run_exec(docker, &exec.id, &tx))
+        .await
+        .map_err(|_| anyhow!("timed out after {}s", timeout.as_secs()))??;
+    drop(tx);
+    let stdout = rx
+        .filter_map(|event| async move {
+            match event {
+                IoEvent::Stdout(line) => Some(line),
+                _ => None,
+            }
+        })
+        .collect()
+        .await;
+    Ok((code, stdout))
+}
+
+/// Sends the output line by line and returns the exit code.
+async fn run_exec(
+    docker: &Docker,
+    exec_id: &str,
+    tx: &UnboundedSender<IoEvent>,
+) -> anyhow::Result<Option<i64>> {
+    let started = docker
+        .start_exec(
+            exec_id,
+            Some(StartExecOptions {
+                detach: false,
+                ..Default::default()
+            }),
+        )
+        .await?;
+    let StartExecResults::Attached { mut output, .. } = started else {
+        return Err(anyhow!("exec was started detached"));
+    };
+
+    let mut stdout = Lines::default();
+    let mut stderr = Lines::default();
+    while let Some(out) = output.next().await {
+        match out? {
+            LogOutput::StdOut { message } => stdout.push(&message, tx, IoEvent::Stdout),
+            LogOutput::StdErr { message } => stderr.push(&message, tx, IoEvent::Stderr),
+            _ => {}
+        }
+    }
+    stdout.flush(tx, IoEvent::Stdout);
+    stderr.flush(tx, IoEvent::Stderr);
+
+    Ok(docker.inspect_exec(exec_id).await?.exit_code)
+}
+
+/// Docker does not align output chunks with lines.
+#[derive(Default)]
+struct Lines(String);
+
+impl Lines {
+    fn push(&mut self, chunk: &[u8], tx: &UnboundedSender<IoEvent>, event: fn(String) -> IoEvent) {
+        self.0.push_str(&String::from_utf8_lossy(chunk));
+        while let Some(end) = self.0.find('\n') {
+            let line: String = self.0.drain(..=end).collect();
+            let _ = tx.unbounded_send(event(line.trim_end_matches(['\n', '\r']).to_owned()));
+        }
+        if self.0.len() >= MAX_LINE {
+            let _ = tx.unbounded_send(event(std::mem::take(&mut self.0)));
+        }
+    }
+
+    fn flush(self, tx: &UnboundedSender<IoEvent>, event: fn(String) -> IoEvent) {
+        if !self.0.is_empty() {
+            let _ = tx.unbounded_send(event(self.0));
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::{Component, Path as FsPath, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Multipart, Path, Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use bollard::{
+    container::{DownloadFromContainerOptions, UploadToContainerOptions},
+    errors::Error as DockerError,
+    Docker,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tar::EntryType;
+use tokio::fs;
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+use tracing::{debug, info};
+use ulid::Ulid;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
+use crate::api::error::{ApiError, ApiErrorVariant};
+use crate::config::TEMP_DOWNLOAD_FOLDER;
+use crate::SharedAppState;
+
+use super::exec::exec_output;
+use super::{
+    checked_stream, docker_error, file_body, spool_field, BodyError, BodyStream, CrudReturn,
+};
+
+const TAR_BLOCK: u64 = 512;
+
+#[derive(Debug, Deserialize)]
+pub struct FileParams {
+    /// Absolute path inside the container
+    path: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    #[default]
+    Tar,
+    Zip,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadParams {
+    path: String,
+    #[serde(default)]
+    format: ArchiveFormat,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileEntry {
+    name: String,
+    kind: FileKind,
+    size: u64,
+    mode: u32,
+    /// Unix seconds
+    modified: u64,
+    /// Target of a symlink
+    link: Option<String>,
+}
+
+fn check_path(path: &str) -> Result<(), ApiError> {
+    if !path.starts_with('/') {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("path {path:?} must be absolute"),
+        ));
+    }
+    Ok(())
+}
+
+async fn archive(
+    docker: &Docker,
+    id: &str,
+    path: &str,
+) -> Result<impl Stream<Item = Result<Bytes, DockerError>> + Send + 'static, ApiError> {
+    checked_stream(docker.download_from_container(
+        id,
+        Some(DownloadFromContainerOptions {
+            path: path.to_owned(),
+        }),
+    ))
+    .await
+}
+
+/// A blocking reader over the archive, for the `tar` crate.
+fn blocking_reader(
+    archive: impl Stream<Item = Result<Bytes, DockerError>> + Send + 'static,
+) -> impl Read + Send + 'static {
+    let archive = Box::pin(archive.map(|r| r.map_err(io::Error::other)));
+    SyncIoBridge::new(StreamReader::new(archive))
+}
+
+/// Lists the children of `$1` as `<raw mode in hex> <size> <mtime> <path>`, followed by `-> <path>` and the
+/// target for every symlink. Works with both GNU and busybox tools.
+const LIST_SCRIPT: &str = r#"[ -e "$1" ] || exit 3
+[ -d "$1" ] || exit 2
+cd -- "$1" || exit 1
+find . -mindepth 1 -maxdepth 1 -exec stat -c '%f %s %Y %n' {} + || exit 1
+find . -mindepth 1 -maxdepth 1 -type l -exec sh -c 'for l; do printf "%s\n%s\n" "-> $l" "$(readlink "$l")"; done' sh {} +"#;
+const LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Docker only hands out a directory as a tar of its whole subtree, which has to be read to the end to list it.
+/// Listing that way gives up once the archive gets larger than this.
+const MAX_LISTED_ARCHIVE: u64 = 64 * 1024 * 1024;
+
+/// Lists a directory with `find` and `stat` in the container, which only reads the directory itself.
+/// That starts a process in the container, so it is only done for users who may run commands there.
+/// Everyone else, containers without those tools, stopped ones and directories the container user may not read
+/// get the listing from the archive of the directory.
+pub async fn list_files(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(id): Path<String>,
+    Query(params): Query<FileParams>,
+) -> CrudReturn {
+    check_path(&params.path)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &id,
+        Access::View,
+    )
+    .await?;
+
+    let docker = state.docker.lock_owned().await.clone();
+    let may_exec = user.has_scope(Scope::ContainersWrite)
+        && user
+            .require_resource(&docker, ResourceKind::Container, &id, Access::Deploy)
+            .await
+            .is_ok();
+    if may_exec {
+        if let Some(entries) = list_with_find(&docker, &id, &params.path).await? {
+            return Ok((StatusCode::OK, Json(json!(entries))));
+        }
+    }
+
+    let reader = blocking_reader(archive(&docker, &id, &params.path).await?);
+    let is_root = FsPath::new(&params.path).file_name().is_none();
+    let entries = tokio::task::spawn_blocking(move || list_archive(reader, is_root))
+        .await?
+        .to_apierror(StatusCode::BAD_REQUEST)?;
+
+    Ok((StatusCode::OK, Json(json!(entries))))
+}
+
+/// Runs `LIST_SCRIPT`, `None` when the directory has to be listed from its archive instead.
+async fn list_with_find(
+    docker: &Docker,
+    id: &str,
+    path: &str,
+) -> Result<Option<Vec<FileEntry>>, ApiError> {
+    let cmd = ["sh", "-c", LIST_SCRIPT, "sh", path]
+        .map(str::to_owned)
+        .to_vec();
+    match exec_output(docker, id, cmd, None, LIST_TIMEOUT).await {
+        Ok((Some(0), lines)) => return Ok(Some(parse_listing(&lines))),
+        Ok((Some(2), _)) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("{path} is not a directory"),
+            ))
+        }
+        Ok((Some(3), _)) => {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                anyhow!("{path} does not exist"),
+            ))
+        }
+        Ok((code, _)) => debug!(container = id, ?code, "listing with find failed"),
+        Err(e) => debug!(container = id, ?e, "could not list with find"),
+    }
+    Ok(None)
+}
+
+/// Reads the output of `LIST_SCRIPT`, lines it can't make sense of (names holding a newline) are skipped.
+fn parse_listing(lines: &[String]) -> Vec<FileEntry> {
+    let mut entries = Vec::new();
+    let mut links = HashMap::new();
+    let mut lines = lines.iter();
+    while let Some(line) = lines.next() {
+        if let Some(path) = line.strip_prefix("-> ") {
+            if let Some(target) = lines.next() {
+                links.insert(path, target.clone());
+            }
+            continue;
+        }
+        let mut fields = line.splitn(4, ' ');
+        let (Some(raw_mode), Some(size), Some(modified), Some(path)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let (Ok(raw_mode), Ok(size), Ok(modified)) = (
+            u32::from_str_radix(raw_mode, 16),
+            size.parse::<u64>(),
+            modified.parse::<u64>(),
+        ) else {
+            continue;
+        };
+        let kind = match raw_mode & 0o170000 {
+            0o100000 => FileKind::File,
+            0o040000 => FileKind::Dir,
+            0o120000 => FileKind::Symlink,
+            _ => FileKind::Other,
+        };
+        entries.push((
+            path,
+            FileEntry {
+                name: path.trim_start_matches("./").to_owned(),
+                kind,
+                size,
+                mode: raw_mode & 0o7777,
+                modified,
+                link: None,
+            },
+        ));
+    }
+    entries
+        .into_iter()
+        .map(|(path, mut entry)| {
+            entry.link = links.remove(path);
+            entry
+        })
+        .collect()
+}
+
+fn list_archive(reader: impl Read, is_root: bool) -> anyhow::Result<Vec<FileEntry>> {
+    let mut limited = reader.take(MAX_LISTED_ARCHIVE);
+    let entries = archive_children(&mut limited, is_root);
+    if limited.limit() == 0 {
+        bail!(
+            "the directory holds more than {} MiB, download it instead",
+            MAX_LISTED_ARCHIVE / 1024 / 1024
+        );
+    }
+    entries
+}
+
+/// Direct children of the archived directory, the archive starts with the directory itself unless it is `/`.
+fn archive_children(reader: impl Read, is_root: bool) -> anyhow::Result<Vec<FileEntry>> {
+    let depth = if is_root { 1 } else { 2 };
+    let mut entries = Vec::new();
+    for entry in tar::Archive::new(reader).entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        let components: Vec<_> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(n) => Some(n.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let header = entry.header();
+
+        if components.len() == 1 && !is_root && !header.entry_type().is_dir() {
+            bail!("{} is not a directory", components[0]);
+        }
+        if components.len() != depth {
+            continue;
+        }
+
+        let kind = match header.entry_type() {
+            EntryType::Regular | EntryType::Continuous => FileKind::File,
+            EntryType::Directory => FileKind::Dir,
+            EntryType::Symlink => FileKind::Symlink,
+            _ => FileKind::Other,
+        };
+        entries.push(FileEntry {
+            name: components[depth - 1].clone(),
+            kind,
+            size: header.size()?,
+            mode: header.mode()?,
+            modified: header.mtime()?,
+            link: entry.link_name()?.map(|l| l.to_string_lossy().into_owned()),
+        });
+    }
+    Ok(entries)
+}
+
+/// Downloads a file or directory as a tar (streamed as docker sends it) or zip archive.
+pub async fn download_files(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(id): Path<String>,
+    Query(params): Query<DownloadParams>,
+) -> Result<Response, ApiError> {
+    check_path(&params.path)?;
+    // Files can hold secrets that are redacted everywhere else
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &id,
+        Access::Deploy,
+    )
+    .await?;
+
+    let docker = state.docker.lock_owned().await.clone();
+    let archive = archive(&docker, &id, &params.path).await?;
+    info!(container = id, path = params.path, format = ?params.format, username = user.username, "downloading files");
+
+    let name = FsPath::new(&params.path)
+        .file_name()
+        .map_or("root".to_owned(), |n| n.to_string_lossy().into_owned());
+    let (body, content_type, extension) = match params.format {
+        ArchiveFormat::Tar => (Body::from_stream(archive), "application/x-tar", "tar"),
+        ArchiveFormat::Zip => {
+            // Zip needs the sizes up front, so it is built in a temporary file
+            fs::create_dir_all(TEMP_DOWNLOAD_FOLDER).await?;
+            let temp = PathBuf::from(TEMP_DOWNLOAD_FOLDER).join(format!("{}.zip", Ulid::new()));
+            let reader = blocking_reader(archive);
+            let out = fs::File::create(&temp).await?.into_std().await;
+            let converted = tokio::task::spawn_blocking(move || tar_to_zip(reader, out)).await?;
+            let file = fs::File::open(&temp).await;
+            // The open file stays readable after removing it
+            let _ = fs::remove_file(&temp).await;
+            converted?;
+            (
+                Body::from_stream(ReaderStream::new(file?)),
+                "application/zip",
+                "zip",
+            )
+        }
+    };
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{name}.{extension}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+fn tar_to_zip(reader: impl Read, out: std::fs::File) -> anyhow::Result<()> {
+    let mut zip = ZipWriter::new(out);
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let options = SimpleFileOptions::default().unix_permissions(entry.header().mode()?);
+        match entry.header().entry_type() {
+            EntryType::Directory => zip.add_directory(path, options)?,
+            EntryType::Regular | EntryType::Continuous => {
+                let large = entry.size() > u64::from(u32::MAX);
+                zip.start_file(path, options.large_file(large))?;
+                io::copy(&mut entry, &mut zip)?;
+            }
+            EntryType::Symlink => {
+                if let Some(target) = entry.link_name()? {
+                    zip.add_symlink(path, target.to_string_lossy(), options)?;
+                }
+            }
+            // Hard links, devices and fifos have no zip equivalent
+            _ => {}
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Multipart upload into the directory `path`. Tar files are extracted there, other files are copied with their name.
+pub async fn upload_files(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(id): Path<String>,
+    Query(params): Query<FileParams>,
+    mut multipart: Multipart,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    check_path(&params.path)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &id,
+        Access::Deploy,
+    )
+    .await?;
+
+    let docker = state.docker.lock_owned().await.clone();
+    let mut uploaded = Vec::new();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .to_apierror(StatusCode::BAD_REQUEST)?
+    {
+        let Some(name) = field.file_name().map(str::to_owned) else {
+            continue;
+        };
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("invalid file name {name:?}"),
+            ));
+        }
+        let is_tar = name.ends_with(".tar") || field.content_type() == Some("application/x-tar");
+        let (file, size) = spool_field(field).await?;
+        let tar = if is_tar {
+            file_body(file)
+        } else {
+            single_file_tar(&name, file, size).to_apierror(StatusCode::BAD_REQUEST)?
+        };
+
+        docker
+            .upload_to_container(
+                &id,
+                Some(UploadToContainerOptions {
+                    path: params.path.clone(),
+                    ..Default::default()
+                }),
+                tar.into(),
+            )
+            .await
+            .map_err(docker_error)?;
+        info!(
+            container = id,
+            path = params.path,
+            file = name,
+            username = user.username,
+            "uploaded file"
+        );
+        uploaded.push(name);
+    }
+
+    if uploaded.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("no files in the upload"),
+        ));
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "path": params.path, "uploaded": uploaded })),
+    ))
+}
+
+/// A tar holding just `file`, streamed around it without reading it into memory.
+fn single_file_tar(name: &str, file: fs::File, size: u64) -> io::Result<BodyStream> {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(name)?;
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+
+    // Entries are padded to whole blocks and the archive ends with two empty ones
+    let padding = (TAR_BLOCK - size % TAR_BLOCK) % TAR_BLOCK;
+    let end = vec![0; (padding + 2 * TAR_BLOCK) as usize];
+    let head = stream::once(async move { Ok(Bytes::copy_from_slice(header.as_bytes())) });
+    let tail = stream::once(async move { Ok(Bytes::from(end)) });
+    let tar = head.chain(ReaderStream::new(file)).chain(tail);
+    Ok(Box::new(
+        tar.map(|r: io::Result<Bytes>| r.map_err(BodyError::from)),
+    ))
+}
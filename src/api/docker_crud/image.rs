@@ -1,41 +1,188 @@
 use axum::extract::Path;
 use axum::Json;
 use axum::{extract::State, http::StatusCode};
+use axum_extra::extract::Query;
 
-use bollard::image::{ListImagesOptions, PruneImagesOptions, RemoveImageOptions};
+use bollard::image::{
+    CreateImageOptions, ListImagesOptions, PruneImagesOptions, RemoveImageOptions,
+};
+use bollard::models::ImageSummary;
+use bollard::Docker;
+use chrono::DateTime;
+use futures::stream::StreamExt;
 use serde_json::json;
 
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
 use crate::SharedAppState;
 
+use super::listing::{ListKind, ListParams, ListReturn, SortKey};
+use super::prune::{known_size, PruneFilters, PruneItem, PruneKind, PruneParams, PruneReport};
 use super::CrudReturn;
 
-pub async fn images(State(state): State<SharedAppState>) -> CrudReturn {
-    let ret = state
+pub async fn images(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<ListParams>,
+) -> ListReturn {
+    let filters = params.docker_filters(ListKind::Image)?;
+    let mut ret = state
         .docker
         .lock_owned()
         .await
         .list_images(Some(ListImagesOptions::<String> {
             all: true,
+            filters,
             ..Default::default()
         }))
         .await?;
-    Ok((StatusCode::OK, Json(json!(&ret))))
+    ret.retain(|i| user.can_see(Some(&i.labels)));
+    params.respond(ret, |i, key| match key {
+        SortKey::Created => Some(i.created).into(),
+        SortKey::Size => Some(i.size).into(),
+        SortKey::Name => i.repo_tags.first().cloned().into(),
+    })
 }
 
-pub async fn prune_images(State(state): State<SharedAppState>) -> CrudReturn {
-    let ret = state
-        .docker
-        .lock_owned()
-        .await
-        .prune_images(None::<PruneImagesOptions<String>>)
+/// Repository of an image reference, optionally with a registry host: `registry.example.com:5000/team/app`
+pub fn valid_repository(repo: &str) -> bool {
+    let mut parts: Vec<&str> = repo.split('/').collect();
+    if parts.len() > 1 && (parts[0].contains(['.', ':']) || parts[0] == "localhost") {
+        let host = parts.remove(0);
+        let (name, port) = host.split_once(':').unwrap_or((host, "1"));
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'))
+            || port.parse::<u16>().is_err()
+        {
+            return false;
+        }
+    }
+    parts.iter().all(|p| {
+        p.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && p.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-')
+            })
+    })
+}
+
+/// Docker's rule for image tags
+pub fn valid_tag(tag: &str) -> bool {
+    tag.len() <= 128
+        && tag
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// The daemon pulls every tag of a repository when none is given.
+pub fn with_default_tag(image: &str) -> String {
+    let name = image.rsplit('/').next().unwrap_or(image);
+    if name.contains([':', '@']) {
+        image.to_owned()
+    } else {
+        format!("{image}:latest")
+    }
+}
+
+/// Pulls an image and waits until it is done.
+pub async fn pull_image(docker: &Docker, image: &str) -> anyhow::Result<()> {
+    let image = with_default_tag(image);
+    let mut progress = docker.create_image(
+        Some(CreateImageOptions {
+            from_image: image.as_str(),
+            ..Default::default()
+        }),
+        None,
+        None,
+    );
+    while let Some(info) = progress.next().await {
+        info?;
+    }
+    Ok(())
+}
+
+pub async fn prune_images(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<PruneParams>,
+) -> CrudReturn {
+    user.require_scope(Scope::ResourcesWrite)?;
+    user.require_global()?;
+    let filters = params.filters(PruneKind::Image)?;
+    let docker = state.docker.lock_owned().await;
+
+    let plan = plan_image_prune(&docker, &filters).await?;
+    if params.dry_run {
+        return Ok((StatusCode::OK, Json(json!(PruneReport::planned(plan)))));
+    }
+
+    let ret = docker
+        .prune_images(Some(PruneImagesOptions {
+            filters: filters.docker_filters(PruneKind::Image),
+        }))
         .await?;
-    Ok((StatusCode::OK, Json(json!(&ret))))
+    // Docker also lists every removed layer, only keep the images themselves
+    let deleted: Vec<String> = ret
+        .images_deleted
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|i| i.deleted)
+        .filter(|d| plan.iter().any(|p| p.id == *d))
+        .collect();
+    let report = PruneReport::executed(plan, &deleted, ret.space_reclaimed.and_then(known_size));
+    Ok((StatusCode::OK, Json(json!(report))))
+}
+
+/// Same selection as the daemon: (dangling) images that no container uses.
+async fn plan_image_prune(
+    docker: &Docker,
+    filters: &PruneFilters,
+) -> anyhow::Result<Vec<PruneItem>> {
+    let usage = docker.df().await?;
+    Ok(usage
+        .images
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|i| i.containers == 0 && (!filters.dangling || is_dangling(i)))
+        .filter(|i| filters.matches_labels(Some(&i.labels)))
+        .filter(|i| filters.created_before_until(DateTime::from_timestamp(i.created, 0)))
+        .map(|i| PruneItem {
+            // Only the layers not shared with other images are freed
+            size: known_size(if i.shared_size > 0 {
+                i.size - i.shared_size
+            } else {
+                i.size
+            }),
+            name: i.repo_tags.first().cloned(),
+            id: i.id,
+        })
+        .collect())
+}
+
+fn is_dangling(image: &ImageSummary) -> bool {
+    image.repo_tags.iter().all(|t| t == "<none>:<none>")
 }
 
 pub async fn remove_images(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path(name): Path<String>,
 ) -> CrudReturn {
+    user.require_scope(Scope::ResourcesWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Image,
+        &name,
+        Access::Manage,
+    )
+    .await?;
     let ret = state
         .docker
         .lock_owned()
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use axum::{
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::api::error::ApiError;
+
+pub const TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+
+const CONTAINER_STATES: [&str; 7] = [
+    "created",
+    "restarting",
+    "running",
+    "removing",
+    "paused",
+    "exited",
+    "dead",
+];
+
+pub type ListReturn = Result<(StatusCode, HeaderMap, Json<Value>), ApiError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    Container,
+    Image,
+    Volume,
+    Network,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Created,
+    Size,
+    Name,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    /// `key` or `key=value`, can be repeated
+    #[serde(default)]
+    label: Vec<String>,
+    /// Containers only, can be repeated
+    #[serde(default)]
+    status: Vec<String>,
+    /// Substring of the name, or an image reference such as `nginx:*`
+    name: Option<String>,
+    /// Images, volumes and networks
+    dangling: Option<bool>,
+    /// Containers created from this image
+    ancestor: Option<String>,
+    sort: Option<SortKey>,
+    #[serde(default)]
+    order: SortOrder,
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+/// Sortable value of a resource, missing values sort first.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortValue {
+    Missing,
+    Number(i64),
+    Text(String),
+}
+
+impl From<Option<i64>> for SortValue {
+    fn from(value: Option<i64>) -> Self {
+        value.map_or(SortValue::Missing, SortValue::Number)
+    }
+}
+
+impl From<Option<String>> for SortValue {
+    fn from(value: Option<String>) -> Self {
+        value.map_or(SortValue::Missing, SortValue::Text)
+    }
+}
+
+impl ListParams {
+    /// Docker filters for the list call, rejects filters the resource doesn't support.
+    pub fn docker_filters(&self, kind: ListKind) -> Result<HashMap<String, Vec<String>>, ApiError> {
+        self.validate(kind)
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))
+    }
+
+    fn validate(&self, kind: ListKind) -> anyhow::Result<HashMap<String, Vec<String>>> {
+        let mut filters = HashMap::new();
+
+        if let Some(l) = self
+            .label
+            .iter()
+            .find(|l| l.is_empty() || l.starts_with('='))
+        {
+            bail!("invalid label filter {l:?}");
+        }
+        if !self.label.is_empty() {
+            filters.insert("label".to_owned(), self.label.clone());
+        }
+
+        if !self.status.is_empty() {
+            if kind != ListKind::Container {
+                bail!("status only applies to containers");
+            }
+            if let Some(s) = self
+                .status
+                .iter()
+                .find(|s| !CONTAINER_STATES.contains(&s.as_str()))
+            {
+                bail!("invalid status {s:?}, expected one of {CONTAINER_STATES:?}");
+            }
+            filters.insert("status".to_owned(), self.status.clone());
+        }
+
+        if let Some(name) = &self.name {
+            // Images are matched by reference instead of name
+            let key = if kind == ListKind::Image {
+                "reference"
+            } else {
+                "name"
+            };
+            filters.insert(key.to_owned(), vec![name.clone()]);
+        }
+
+        if let Some(dangling) = self.dangling {
+            if kind == ListKind::Container {
+                bail!("dangling does not apply to containers");
+            }
+            filters.insert("dangling".to_owned(), vec![dangling.to_string()]);
+        }
+
+        if let Some(ancestor) = &self.ancestor {
+            if kind != ListKind::Container {
+                bail!("ancestor only applies to containers");
+            }
+            filters.insert("ancestor".to_owned(), vec![ancestor.clone()]);
+        }
+
+        if self.sort == Some(SortKey::Size) && matches!(kind, ListKind::Volume | ListKind::Network)
+        {
+            bail!("{kind:?} can not be sorted by size");
+        }
+        if self.limit == Some(0) {
+            bail!("limit must be at least 1");
+        }
+
+        Ok(filters)
+    }
+
+    pub fn sorts_by(&self, key: SortKey) -> bool {
+        self.sort == Some(key)
+    }
+
+    /// Sorts `items` and returns the requested page with the total count before paging.
+    pub fn page<T>(
+        &self,
+        mut items: Vec<T>,
+        sort_value: impl Fn(&T, SortKey) -> SortValue,
+    ) -> (Vec<T>, usize) {
+        let total = items.len();
+        if let Some(key) = self.sort {
+            items.sort_by_cached_key(|i| sort_value(i, key));
+            if let SortOrder::Desc = self.order {
+                items.reverse();
+            }
+        }
+
+        let page = items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        (page, total)
+    }
+
+    /// Pages `items` into a JSON array, the total count goes in a header.
+    pub fn respond<T: Serialize>(
+        &self,
+        items: Vec<T>,
+        sort_value: impl Fn(&T, SortKey) -> SortValue,
+    ) -> ListReturn {
+        let (page, total) = self.page(items, sort_value);
+        Ok((StatusCode::OK, total_count(total), Json(json!(page))))
+    }
+}
+
+pub fn total_count(total: usize) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(total));
+    headers
+}
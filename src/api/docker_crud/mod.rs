@@ -1,19 +1,108 @@
+use std::{error::Error as StdError, io::SeekFrom, path::PathBuf};
+
+use anyhow::anyhow;
+use axum::body::Bytes;
+use axum::extract::multipart::Field;
 use axum::Json;
 use axum::{extract::State, http::StatusCode};
-
+use bollard::errors::Error as DockerError;
+use futures::{stream, Stream, StreamExt};
 use serde_json::{json, Value};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+use ulid::Ulid;
 
+use crate::config::TEMP_UPLOAD_FOLDER;
 use crate::SharedAppState;
 
-use super::error::ApiError;
+use super::error::{ApiError, ApiErrorVariant};
 
 pub type CrudReturn = Result<(StatusCode, Json<Value>), ApiError>;
 
+pub type BodyError = Box<dyn StdError + Send + Sync>;
+/// A streamed request body for docker, converts into the body type of bollard.
+pub type BodyStream = Box<dyn Stream<Item = Result<Bytes, BodyError>> + Send>;
+
 pub mod container;
+pub mod container_spec;
+pub mod definition;
+pub mod exec;
+pub mod files;
 pub mod image;
+pub mod image_jobs;
+pub mod listing;
 pub mod network;
+pub mod prune;
+pub mod recreate;
+pub mod registry;
+pub mod stats;
 pub mod volume;
 
+/// Keeps the status of docker errors such as a missing path or a name conflict, instead of a 500.
+pub fn docker_error(error: DockerError) -> ApiError {
+    match error {
+        DockerError::DockerResponseServerError {
+            status_code,
+            message,
+        } => ApiError::new(
+            StatusCode::from_u16(status_code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            anyhow!(message),
+        ),
+        e => e.into(),
+    }
+}
+
+/// Docker answers errors of a streamed download (a missing path or container) before the first chunk,
+/// waits for that so they can still be returned with their status.
+pub async fn checked_stream(
+    download: impl Stream<Item = Result<Bytes, DockerError>> + Send + 'static,
+) -> Result<impl Stream<Item = Result<Bytes, DockerError>> + Send + 'static, ApiError> {
+    let mut download = Box::pin(download);
+    let first = download
+        .next()
+        .await
+        .transpose()
+        .map_err(docker_error)?
+        .unwrap_or_default();
+    Ok(stream::once(async { Ok(first) }).chain(download))
+}
+
+/// Writes an uploaded file to disk, so uploads of hundreds of megabytes never sit in memory.
+/// Returns the file positioned at its start and its size, it is already unlinked and goes away once closed.
+pub async fn spool_field(mut field: Field<'_>) -> Result<(File, u64), ApiError> {
+    let mut file = temp_file().await?;
+    let mut size = 0;
+    while let Some(chunk) = field.chunk().await.to_apierror(StatusCode::BAD_REQUEST)? {
+        file.write_all(&chunk).await?;
+        size += chunk.len() as u64;
+    }
+    file.flush().await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok((file, size))
+}
+
+/// A file for data on its way into docker, already unlinked so it goes away once closed.
+pub async fn temp_file() -> std::io::Result<File> {
+    fs::create_dir_all(TEMP_UPLOAD_FOLDER).await?;
+    let path = PathBuf::from(TEMP_UPLOAD_FOLDER).join(Ulid::new().to_string());
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await?;
+    fs::remove_file(&path).await?;
+    Ok(file)
+}
+
+/// Streams a file into a docker request.
+pub fn file_body(file: File) -> BodyStream {
+    Box::new(ReaderStream::new(file).map(|r| r.map_err(BodyError::from)))
+}
+
 pub async fn docker_sys_info(State(state): State<SharedAppState>) -> CrudReturn {
     let ret = state.docker.lock_owned().await.info().await?;
     Ok((StatusCode::OK, Json(json!(&ret))))
@@ -1,40 +1,124 @@
 use axum::extract::Path;
 use axum::Json;
 use axum::{extract::State, http::StatusCode};
+use axum_extra::extract::Query;
 
-use bollard::network::{ListNetworksOptions, PruneNetworksOptions};
+use bollard::network::{InspectNetworkOptions, ListNetworksOptions, PruneNetworksOptions};
+use bollard::Docker;
+use chrono::{DateTime, Utc};
 use serde_json::json;
 
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
 use crate::SharedAppState;
 
+use super::listing::{ListKind, ListParams, ListReturn, SortKey, SortValue};
+use super::prune::{PruneFilters, PruneItem, PruneKind, PruneParams, PruneReport};
 use super::CrudReturn;
 
-pub async fn networks(State(state): State<SharedAppState>) -> CrudReturn {
-    let ret = state
+/// Created by the daemon, never pruned
+const DEFAULT_NETWORKS: [&str; 3] = ["bridge", "host", "none"];
+
+pub async fn networks(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<ListParams>,
+) -> ListReturn {
+    let filters = params.docker_filters(ListKind::Network)?;
+    let mut ret = state
         .docker
         .lock_owned()
         .await
-        .list_networks(Some(ListNetworksOptions::<String> {
-            ..Default::default()
+        .list_networks(Some(ListNetworksOptions::<String> { filters }))
+        .await?;
+    ret.retain(|n| user.can_see(n.labels.as_ref()));
+    params.respond(ret, |n, key| match key {
+        SortKey::Created => n.created.clone().into(),
+        SortKey::Name => n.name.clone().into(),
+        SortKey::Size => SortValue::Missing,
+    })
+}
+
+pub async fn prune_networks(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<PruneParams>,
+) -> CrudReturn {
+    user.require_scope(Scope::ResourcesWrite)?;
+    user.require_global()?;
+    let filters = params.filters(PruneKind::Network)?;
+    let docker = state.docker.lock_owned().await;
+
+    let plan = plan_network_prune(&docker, &filters).await?;
+    if params.dry_run {
+        return Ok((StatusCode::OK, Json(json!(PruneReport::planned(plan)))));
+    }
+
+    let ret = docker
+        .prune_networks(Some(PruneNetworksOptions {
+            filters: filters.docker_filters(PruneKind::Network),
         }))
         .await?;
-    Ok((StatusCode::OK, Json(json!(&ret))))
+    // Networks take no disk space worth reporting
+    let report = PruneReport::executed(plan, &ret.networks_deleted.unwrap_or_default(), Some(0));
+    Ok((StatusCode::OK, Json(json!(report))))
 }
 
-pub async fn prune_networks(State(state): State<SharedAppState>) -> CrudReturn {
-    let ret = state
-        .docker
-        .lock_owned()
-        .await
-        .prune_networks(None::<PruneNetworksOptions<String>>)
+/// Same selection as the daemon: custom networks without connected containers.
+async fn plan_network_prune(
+    docker: &Docker,
+    filters: &PruneFilters,
+) -> anyhow::Result<Vec<PruneItem>> {
+    let networks = docker
+        .list_networks(None::<ListNetworksOptions<String>>)
         .await?;
-    Ok((StatusCode::OK, Json(json!(&ret))))
+
+    let mut plan = Vec::new();
+    for network in networks {
+        let (Some(id), Some(name)) = (network.id, network.name) else {
+            continue;
+        };
+        if DEFAULT_NETWORKS.contains(&name.as_str()) || network.scope.as_deref() == Some("swarm") {
+            continue;
+        }
+        let created = network
+            .created
+            .as_deref()
+            .and_then(|c| DateTime::parse_from_rfc3339(c).ok())
+            .map(|c| c.with_timezone(&Utc));
+        if !filters.matches_labels(network.labels.as_ref())
+            || !filters.created_before_until(created)
+        {
+            continue;
+        }
+        // The list does not include the attached containers
+        let inspected = docker
+            .inspect_network(&id, None::<InspectNetworkOptions<String>>)
+            .await?;
+        if inspected.containers.is_none_or(|c| c.is_empty()) {
+            plan.push(PruneItem {
+                id,
+                name: Some(name),
+                size: None,
+            });
+        }
+    }
+    Ok(plan)
 }
 
 pub async fn remove_network(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path(name): Path<String>,
 ) -> CrudReturn {
+    user.require_scope(Scope::ResourcesWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Network,
+        &name,
+        Access::Manage,
+    )
+    .await?;
     state
         .docker
         .lock_owned()
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::api::error::ApiError;
+
+#[derive(Debug, Deserialize)]
+pub struct PruneParams {
+    /// Only report what would be removed
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Only resources created before this: a timestamp (RFC 3339 or unix seconds) or an age like `24h` or `7d`
+    until: Option<String>,
+    /// Only resources with this label, `key` or `key=value`. Can be repeated
+    #[serde(default)]
+    label: Vec<String>,
+    /// Only resources without this label, `key` or `key=value`. Can be repeated
+    #[serde(default)]
+    label_not: Vec<String>,
+    /// Images: only dangling ones (default) or every unused image
+    dangling: Option<bool>,
+    /// Volumes: named volumes too, not only anonymous ones
+    all: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneKind {
+    Container,
+    Image,
+    Volume,
+    Network,
+}
+
+#[derive(Debug, Clone)]
+struct LabelFilter {
+    key: String,
+    value: Option<String>,
+}
+
+impl LabelFilter {
+    fn parse(raw: &str) -> anyhow::Result<Self> {
+        let (key, value) = match raw.split_once('=') {
+            Some((k, v)) => (k, Some(v.to_owned())),
+            None => (raw, None),
+        };
+        if key.is_empty() || key.chars().any(char::is_whitespace) {
+            bail!("invalid label filter {raw:?}");
+        }
+        Ok(Self {
+            key: key.to_owned(),
+            value,
+        })
+    }
+
+    fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match (labels.get(&self.key), &self.value) {
+            (Some(v), Some(expected)) => v == expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn to_docker(&self) -> String {
+        match &self.value {
+            Some(v) => format!("{}={v}", self.key),
+            None => self.key.clone(),
+        }
+    }
+}
+
+/// Validated filters of a prune, used both for planning and passed on to docker.
+#[derive(Debug, Clone)]
+pub struct PruneFilters {
+    pub until: Option<DateTime<Utc>>,
+    labels: Vec<LabelFilter>,
+    labels_not: Vec<LabelFilter>,
+    pub dangling: bool,
+    pub all: bool,
+}
+
+impl PruneParams {
+    pub fn filters(&self, kind: PruneKind) -> Result<PruneFilters, ApiError> {
+        self.validate(kind)
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))
+    }
+
+    fn validate(&self, kind: PruneKind) -> anyhow::Result<PruneFilters> {
+        // Mirrors the filters each docker prune endpoint accepts
+        if self.until.is_some() && kind == PruneKind::Volume {
+            bail!("volumes can not be pruned by age");
+        }
+        if self.dangling.is_some() && kind != PruneKind::Image {
+            bail!("dangling only applies to images");
+        }
+        if self.all.is_some() && kind != PruneKind::Volume {
+            bail!("all only applies to volumes");
+        }
+
+        Ok(PruneFilters {
+            until: self.until.as_deref().map(parse_time).transpose()?,
+            labels: self
+                .label
+                .iter()
+                .map(|l| LabelFilter::parse(l))
+                .collect::<anyhow::Result<_>>()?,
+            labels_not: self
+                .label_not
+                .iter()
+                .map(|l| LabelFilter::parse(l))
+                .collect::<anyhow::Result<_>>()?,
+            dangling: self.dangling.unwrap_or(true),
+            all: self.all.unwrap_or(false),
+        })
+    }
+}
+
+impl PruneFilters {
+    pub fn matches_labels(&self, labels: Option<&HashMap<String, String>>) -> bool {
+        let empty = HashMap::new();
+        let labels = labels.unwrap_or(&empty);
+        self.labels.iter().all(|l| l.matches(labels))
+            && !self.labels_not.iter().any(|l| l.matches(labels))
+    }
+
+    /// Whether something created at `created` is old enough, unknown creation times are kept.
+    pub fn created_before_until(&self, created: Option<DateTime<Utc>>) -> bool {
+        match (self.until, created) {
+            (None, _) => true,
+            (Some(until), Some(created)) => created < until,
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Filters in the form of bollard's prune options.
+    pub fn docker_filters(&self, kind: PruneKind) -> HashMap<String, Vec<String>> {
+        let mut filters = HashMap::new();
+        if let Some(until) = self.until {
+            filters.insert("until".to_owned(), vec![until.timestamp().to_string()]);
+        }
+        if !self.labels.is_empty() {
+            filters.insert(
+                "label".to_owned(),
+                self.labels.iter().map(LabelFilter::to_docker).collect(),
+            );
+        }
+        if !self.labels_not.is_empty() {
+            filters.insert(
+                "label!".to_owned(),
+                self.labels_not.iter().map(LabelFilter::to_docker).collect(),
+            );
+        }
+        if kind == PruneKind::Image {
+            filters.insert("dangling".to_owned(), vec![self.dangling.to_string()]);
+        }
+        if kind == PruneKind::Volume && self.all {
+            filters.insert("all".to_owned(), vec!["true".to_owned()]);
+        }
+        filters
+    }
+}
+
+/// A timestamp (RFC 3339 or unix seconds) or an age like `24h`, meaning that long ago.
+pub fn parse_time(raw: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(seconds) = raw.parse::<i64>() {
+        return DateTime::from_timestamp(seconds, 0).ok_or(anyhow!("invalid timestamp {raw}"));
+    }
+
+    // An age such as `90m`, `24h` or `7d`
+    let invalid = || anyhow!("invalid time {raw:?}, use a timestamp or an age like 24h");
+    let (unit_at, _) = raw.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = raw.split_at(unit_at);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let age = match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => None,
+    }
+    .filter(|a| *a >= Duration::zero())
+    .ok_or_else(invalid)?;
+    Ok(Utc::now() - age)
+}
+
+/// A resource that is (or would be) removed by a prune.
+#[derive(Debug, Clone, Serialize)]
+pub struct PruneItem {
+    pub id: String,
+    pub name: Option<String>,
+    /// Bytes freed by removing it, when docker knows.
+    pub size: Option<u64>,
+}
+
+impl PruneItem {
+    fn is(&self, id_or_name: &str) -> bool {
+        self.id == id_or_name || self.name.as_deref() == Some(id_or_name)
+    }
+}
+
+/// Returned by both the dry run and the real prune.
+#[derive(Debug, Serialize)]
+pub struct PruneReport {
+    pub dry_run: bool,
+    pub items: Vec<PruneItem>,
+    pub space_reclaimed: u64,
+}
+
+impl PruneReport {
+    pub fn planned(items: Vec<PruneItem>) -> Self {
+        Self {
+            dry_run: true,
+            space_reclaimed: items.iter().filter_map(|i| i.size).sum(),
+            items,
+        }
+    }
+
+    /// Report of a real prune, `deleted` are the ids or names docker says it removed.
+    /// Uses the plan to fill in names and sizes, anything docker removed outside of it is still listed.
+    pub fn executed(
+        plan: Vec<PruneItem>,
+        deleted: &[String],
+        space_reclaimed: Option<u64>,
+    ) -> Self {
+        let mut items: Vec<PruneItem> = plan
+            .into_iter()
+            .filter(|i| deleted.iter().any(|d| i.is(d)))
+            .collect();
+        for d in deleted {
+            if !items.iter().any(|i| i.is(d)) {
+                items.push(PruneItem {
+                    id: d.clone(),
+                    name: None,
+                    size: None,
+                });
+            }
+        }
+
+        Self {
+            dry_run: false,
+            space_reclaimed: space_reclaimed
+                .unwrap_or_else(|| items.iter().filter_map(|i| i.size).sum()),
+            items,
+        }
+    }
+}
+
+/// Docker reports unknown sizes as -1.
+pub fn known_size(size: i64) -> Option<u64> {
+    u64::try_from(size).ok()
+}
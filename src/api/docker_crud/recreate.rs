@@ -0,0 +1,438 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use bollard::{
+    container::{
+        Config, CreateContainerOptions, NetworkingConfig, RemoveContainerOptions,
+        RenameContainerOptions, StartContainerOptions, StopContainerOptions,
+    },
+    models::{
+        ContainerInspectResponse, EndpointSettings, HostConfig, Mount, MountPointTypeEnum,
+        MountTypeEnum,
+    },
+    network::ConnectNetworkOptions,
+    Docker,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
+use crate::api::error::{ApiError, ApiErrorVariant};
+use crate::config::{BRANCH_LABEL, PROJECT_LABEL};
+use crate::SharedAppState;
+
+use super::container::require_attachable;
+use super::container_spec::{
+    exposed_ports, port_bindings, validate_mounts, validate_ports, MountKind, MountSpec, PortSpec,
+};
+use super::image::{pull_image, valid_tag};
+use super::{docker_error, CrudReturn};
+
+/// How long the replacement has to keep running before the old container is removed
+const STARTUP_GRACE: Duration = Duration::from_secs(3);
+
+/// Body of `POST /containers/:name/recreate`, anything not set is copied from the old container.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecreatePatch {
+    /// Another image, e.g. `app:2.0`
+    image: Option<String>,
+    /// Only another tag of the current image
+    tag: Option<String>,
+    /// Vars to set, `null` removes one
+    #[serde(default)]
+    env: HashMap<String, Option<String>>,
+    /// Labels to set, `null` removes one
+    #[serde(default)]
+    labels: HashMap<String, Option<String>>,
+    /// Replaces all published ports
+    ports: Option<Vec<PortSpec>>,
+    /// Replaces all mounts, anonymous volumes are kept unless a mount takes their place
+    mounts: Option<Vec<MountSpec>>,
+    /// Pull the image before creating the replacement
+    #[serde(default)]
+    pull: bool,
+}
+
+impl RecreatePatch {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.image.is_some() && self.tag.is_some() {
+            bail!("set either image or tag");
+        }
+        if let Some(image) = &self.image {
+            if image.trim().is_empty() || image.chars().any(char::is_whitespace) {
+                bail!("invalid image {image:?}");
+            }
+        }
+        if let Some(tag) = &self.tag {
+            if !valid_tag(tag) {
+                bail!("invalid tag {tag:?}");
+            }
+        }
+        if let Some(key) = self.env.keys().find(|k| k.is_empty() || k.contains('=')) {
+            bail!("invalid env var name {key:?}");
+        }
+        validate_ports(self.ports.as_deref().unwrap_or_default())?;
+        validate_mounts(self.mounts.as_deref().unwrap_or_default())
+    }
+
+    fn needs_admin(&self) -> bool {
+        self.mounts
+            .iter()
+            .flatten()
+            .any(|m| m.kind == MountKind::Bind)
+    }
+}
+
+/// Replaces a container by one with the same name and networks but a patched configuration.
+/// The old container is renamed and stopped first, and comes back when the replacement fails to start or exits right away.
+pub async fn recreate_container(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(name): Path<String>,
+    Json(patch): Json<RecreatePatch>,
+) -> CrudReturn {
+    user.require_scope(Scope::ContainersWrite)?;
+    patch.validate().to_apierror(StatusCode::BAD_REQUEST)?;
+    if patch.needs_admin() {
+        user.require_admin()?;
+    }
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &name,
+        Access::Manage,
+    )
+    .await?;
+
+    let docker = state.docker.lock_owned().await.clone();
+    let old = docker
+        .inspect_container(&name, None)
+        .await
+        .map_err(docker_error)?;
+    let config = patched_config(&old, &patch).to_apierror(StatusCode::BAD_REQUEST)?;
+
+    // The replacement has to stay within the user's projects as well
+    let labels = config.labels.clone().unwrap_or_default();
+    match labels.get(PROJECT_LABEL) {
+        Some(project) => user.require_project(
+            project,
+            labels.get(BRANCH_LABEL).map(String::as_str),
+            Access::Deploy,
+        )?,
+        None => user.require_global()?,
+    }
+    if let Some(mounts) = &patch.mounts {
+        require_attachable(&user, &docker, mounts, &[]).await?;
+    }
+
+    if patch.pull {
+        if let Some(image) = &config.image {
+            pull_image(&docker, image).await?;
+        }
+    }
+
+    let old_id = old.id.clone().unwrap_or_default();
+    let old_name = old
+        .name
+        .as_deref()
+        .unwrap_or(&name)
+        .trim_start_matches('/')
+        .to_owned();
+    let was_running = old.state.as_ref().and_then(|s| s.running).unwrap_or(false);
+
+    // Free the name (and any static address) for the replacement
+    let parked = format!("{old_name}-replaced-{}", &old_id[..old_id.len().min(12)]);
+    docker
+        .rename_container(&old_id, RenameContainerOptions { name: &parked })
+        .await
+        .map_err(docker_error)?;
+    if was_running {
+        if let Err(e) = docker
+            .stop_container(&old_id, None::<StopContainerOptions>)
+            .await
+        {
+            restore(&docker, &old_id, &old_name, false).await;
+            return Err(docker_error(e));
+        }
+    }
+
+    let new_id = match create_replacement(&docker, &old_name, config, &old).await {
+        Ok(id) => id,
+        Err((id, e)) => {
+            if let Some(id) = id {
+                remove(&docker, &id).await;
+            }
+            restore(&docker, &old_id, &old_name, was_running).await;
+            return Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.context("recreating failed, the old container is back"),
+            ));
+        }
+    };
+
+    remove(&docker, &old_id).await;
+    info!(
+        container = old_name,
+        old_id,
+        new_id,
+        username = user.username,
+        "recreated container"
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "id": new_id, "name": old_name, "replaced": old_id })),
+    ))
+}
+
+/// Returns the new id, and the id of a half created container on errors.
+async fn create_replacement(
+    docker: &Docker,
+    name: &str,
+    mut config: Config<String>,
+    old: &ContainerInspectResponse,
+) -> Result<String, (Option<String>, anyhow::Error)> {
+    let old_id = old.id.as_deref().unwrap_or_default();
+    let networks = old
+        .network_settings
+        .as_ref()
+        .and_then(|n| n.networks.clone())
+        .unwrap_or_default();
+    let primary = match config
+        .host_config
+        .as_ref()
+        .and_then(|h| h.network_mode.as_deref())
+    {
+        None | Some("default") => "bridge".to_owned(),
+        Some(mode) => mode.to_owned(),
+    };
+
+    // Only one network can be given on creation, the others are connected afterwards
+    config.networking_config = networks.get(&primary).map(|e| NetworkingConfig {
+        endpoints_config: HashMap::from([(primary.clone(), endpoint(e, old_id))]),
+    });
+    let created = docker
+        .create_container(
+            Some(CreateContainerOptions {
+                name: name.to_owned(),
+                ..Default::default()
+            }),
+            config,
+        )
+        .await
+        .map_err(|e| (None, e.into()))?;
+
+    let id = created.id;
+    let fail = |e: anyhow::Error| (Some(id.clone()), e);
+    for (network, settings) in networks.iter().filter(|(n, _)| **n != primary) {
+        docker
+            .connect_network(
+                network,
+                ConnectNetworkOptions {
+                    container: id.as_str(),
+                    endpoint_config: endpoint(settings, old_id),
+                },
+            )
+            .await
+            .with_context(|| format!("could not connect network {network}"))
+            .map_err(fail)?;
+    }
+    docker
+        .start_container(&id, None::<StartContainerOptions<String>>)
+        .await
+        .context("the new container did not start")
+        .map_err(fail)?;
+
+    // A bad env, port or entrypoint often only shows once the container exits right after starting
+    tokio::time::sleep(STARTUP_GRACE).await;
+    let state = docker
+        .inspect_container(&id, None)
+        .await
+        .context("could not check the new container")
+        .map_err(fail)?
+        .state
+        .unwrap_or_default();
+    if state.running != Some(true) {
+        let mut reason = format!(
+            "the new container exited right after starting with code {}",
+            state.exit_code.unwrap_or_default()
+        );
+        if let Some(error) = state.error.filter(|e| !e.is_empty()) {
+            reason.push_str(&format!(": {error}"));
+        }
+        return Err(fail(anyhow!(reason)));
+    }
+    Ok(id)
+}
+
+/// Settings of a network the old container was on, without its runtime state.
+fn endpoint(old: &EndpointSettings, old_id: &str) -> EndpointSettings {
+    EndpointSettings {
+        // Docker adds the short id as an alias itself
+        aliases: old.aliases.as_ref().map(|a| {
+            a.iter()
+                .filter(|a| !old_id.starts_with(a.as_str()))
+                .cloned()
+                .collect()
+        }),
+        ipam_config: old.ipam_config.clone(),
+        links: old.links.clone(),
+        driver_opts: old.driver_opts.clone(),
+        ..Default::default()
+    }
+}
+
+fn patched_config(
+    old: &ContainerInspectResponse,
+    patch: &RecreatePatch,
+) -> anyhow::Result<Config<String>> {
+    let mut config: Config<String> = old
+        .config
+        .clone()
+        .ok_or_else(|| anyhow!("container has no config"))?
+        .into();
+    let mut host_config = old.host_config.clone().unwrap_or_default();
+
+    // Docker sets the short id as hostname, which would otherwise stick to the old container
+    let old_id = old.id.as_deref().unwrap_or_default();
+    if config
+        .hostname
+        .as_ref()
+        .is_some_and(|h| old_id.starts_with(h.as_str()))
+    {
+        config.hostname = None;
+    }
+
+    if let Some(image) = &patch.image {
+        config.image = Some(image.clone());
+    }
+    if let Some(tag) = &patch.tag {
+        let image = config.image.as_deref().unwrap_or_default();
+        // A colon after the last slash starts the tag, before it is a registry port
+        let repo = match image.rsplit_once(':') {
+            Some((repo, t)) if !t.contains('/') => repo,
+            _ => image,
+        };
+        let repo = repo.split('@').next().unwrap_or(repo);
+        config.image = Some(format!("{repo}:{tag}"));
+    }
+
+    let mut env: Vec<String> = config.env.take().unwrap_or_default();
+    for (key, value) in &patch.env {
+        env.retain(|e| e.split_once('=').map_or(e.as_str(), |(k, _)| k) != key);
+        if let Some(value) = value {
+            env.push(format!("{key}={value}"));
+        }
+    }
+    config.env = Some(env);
+
+    let mut labels = config.labels.take().unwrap_or_default();
+    for (key, value) in &patch.labels {
+        match value {
+            Some(v) => labels.insert(key.clone(), v.clone()),
+            None => labels.remove(key),
+        };
+    }
+    config.labels = Some(labels);
+
+    if let Some(ports) = &patch.ports {
+        config.exposed_ports = Some(exposed_ports(ports));
+        host_config.port_bindings = Some(port_bindings(ports));
+        host_config.publish_all_ports = None;
+    }
+    if let Some(mounts) = &patch.mounts {
+        host_config.binds = None;
+        host_config.mounts = Some(mounts.iter().map(MountSpec::to_mount).collect());
+    }
+    keep_anonymous_volumes(old, &mut host_config);
+
+    config.host_config = Some(host_config);
+    Ok(config)
+}
+
+/// Anonymous volumes would otherwise be left behind with the old container and replaced by empty ones.
+fn keep_anonymous_volumes(old: &ContainerInspectResponse, host_config: &mut HostConfig) {
+    let binds = host_config.binds.clone().unwrap_or_default();
+    let mounts = host_config.mounts.clone().unwrap_or_default();
+    let named = |volume: &str| {
+        binds.iter().any(|b| b.split(':').next() == Some(volume))
+            || mounts.iter().any(|m| m.source.as_deref() == Some(volume))
+    };
+    let taken = |target: &str| {
+        binds
+            .iter()
+            .any(|b| b.split(':').nth(1).is_some_and(|t| t == target))
+            || mounts
+                .iter()
+                .any(|m| m.target.as_deref() == Some(target) && m.source.is_some())
+    };
+
+    for mount in old.mounts.iter().flatten() {
+        let (Some(MountPointTypeEnum::VOLUME), Some(volume), Some(target)) = (
+            mount.typ,
+            mount.name.as_deref(),
+            mount.destination.as_deref(),
+        ) else {
+            continue;
+        };
+        if named(volume) || taken(target) {
+            continue;
+        }
+        // Drop the anonymous declaration of the same path, `-v /data` or a mount without source
+        if let Some(binds) = host_config.binds.as_mut() {
+            binds.retain(|b| b != target);
+        }
+        let mounts = host_config.mounts.get_or_insert_with(Vec::new);
+        mounts.retain(|m| m.target.as_deref() != Some(target));
+        mounts.push(Mount {
+            target: Some(target.to_owned()),
+            source: Some(volume.to_owned()),
+            typ: Some(MountTypeEnum::VOLUME),
+            read_only: mount.rw.map(|rw| !rw),
+            ..Default::default()
+        });
+    }
+}
+
+async fn remove(docker: &Docker, id: &str) {
+    if let Err(e) = docker
+        .remove_container(
+            id,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+    {
+        warn!(?e, id, "could not remove container");
+    }
+}
+
+/// Gives the old container its name back and starts it again.
+async fn restore(docker: &Docker, id: &str, name: &str, start: bool) {
+    if let Err(e) = docker
+        .rename_container(id, RenameContainerOptions { name })
+        .await
+    {
+        error!(
+            ?e,
+            id, name, "could not restore the name of the old container"
+        );
+    }
+    if start {
+        if let Err(e) = docker
+            .start_container(id, None::<StartContainerOptions<String>>)
+            .await
+        {
+            error!(?e, id, name, "could not restart the old container");
+        }
+    }
+}
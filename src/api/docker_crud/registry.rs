@@ -0,0 +1,145 @@
+use anyhow::bail;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use bollard::auth::DockerCredentials;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+use crate::api::auth::CurrentUser;
+use crate::api::error::ApiErrorVariant;
+use crate::secrets::SecretStore;
+use crate::SharedAppState;
+
+use super::image_jobs::registry_host;
+use super::CrudReturn;
+
+/// Registry of images without a host in their name
+const DOCKER_HUB: &str = "docker.io";
+
+/// Login for a registry, kept in the secret store.
+#[derive(Serialize, Deserialize)]
+pub struct RegistryCredential {
+    username: String,
+    password: String,
+}
+
+/// Body of `PUT /registries`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewRegistry {
+    /// `host[:port]`, `docker.io` for Docker Hub
+    server_address: String,
+    username: String,
+    password: String,
+}
+
+impl NewRegistry {
+    fn validate(&self) -> anyhow::Result<()> {
+        let (host, port) = self
+            .server_address
+            .split_once(':')
+            .unwrap_or((&self.server_address, "1"));
+        if host.is_empty()
+            || !host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'))
+            || port.parse::<u16>().is_err()
+        {
+            bail!(
+                "invalid registry {:?}, expected host[:port]",
+                self.server_address
+            );
+        }
+        if self.username.is_empty() {
+            bail!("username can not be empty");
+        }
+        Ok(())
+    }
+}
+
+pub fn registry_secret_key(server_address: &str) -> String {
+    format!("registry/{server_address}")
+}
+
+/// Stored login for the registry of `image`, if any.
+pub async fn registry_credentials(
+    secrets: &SecretStore,
+    image: &str,
+) -> anyhow::Result<Option<DockerCredentials>> {
+    let server_address = registry_host(image).unwrap_or_else(|| DOCKER_HUB.to_owned());
+    let credential: Option<RegistryCredential> =
+        secrets.get(&registry_secret_key(&server_address)).await?;
+    Ok(credential.map(|c| DockerCredentials {
+        username: Some(c.username),
+        password: Some(c.password),
+        serveraddress: Some(server_address),
+        ..Default::default()
+    }))
+}
+
+/// Registries with stored credentials, without the passwords.
+pub async fn registries(State(state): State<SharedAppState>, user: CurrentUser) -> CrudReturn {
+    user.require_global()?;
+    let mut ret = Vec::new();
+    for key in state.secrets.keys(&registry_secret_key("")).await {
+        if let Some(credential) = state.secrets.get::<RegistryCredential>(&key).await? {
+            ret.push(json!({
+                "server_address": &key[registry_secret_key("").len()..],
+                "username": credential.username,
+            }));
+        }
+    }
+    Ok((StatusCode::OK, Json(json!(ret))))
+}
+
+/// Stores (or replaces) the login for a registry, used by pulls and pushes that bring no credentials of their own.
+pub async fn set_registry(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Json(registry): Json<NewRegistry>,
+) -> CrudReturn {
+    user.require_admin()?;
+    registry.validate().to_apierror(StatusCode::BAD_REQUEST)?;
+    state
+        .secrets
+        .set(
+            &registry_secret_key(&registry.server_address),
+            &RegistryCredential {
+                username: registry.username,
+                password: registry.password,
+            },
+        )
+        .await
+        .to_apierror(StatusCode::SERVICE_UNAVAILABLE)?;
+    info!(
+        registry = registry.server_address,
+        username = user.username,
+        "stored registry credentials"
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "server_address": registry.server_address })),
+    ))
+}
+
+pub async fn remove_registry(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Path(server_address): Path<String>,
+) -> CrudReturn {
+    user.require_admin()?;
+    state
+        .secrets
+        .remove(&registry_secret_key(&server_address))
+        .await?;
+    info!(
+        registry = server_address,
+        username = user.username,
+        "removed registry credentials"
+    );
+    Ok((StatusCode::OK, Json(json!({}))))
+}
@@ -0,0 +1,236 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use bollard::{
+    container::{ListContainersOptions, MemoryStatsStats, Stats, StatsOptions},
+    Docker,
+};
+use futures::{future::join_all, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::watch, time::MissedTickBehavior};
+use tracing::{debug, warn};
+
+/// Resource usage of a container, computed the way `docker stats` does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerStats {
+    pub id: String,
+    pub name: String,
+    /// 100 per fully used core
+    pub cpu_percent: f64,
+    /// Bytes, without the page cache
+    pub memory_usage: u64,
+    pub memory_limit: u64,
+    pub memory_percent: f64,
+    /// Bytes since the container started
+    pub network_rx: u64,
+    pub network_tx: u64,
+    pub block_read: u64,
+    pub block_write: u64,
+    pub pids: u64,
+}
+
+impl From<&Stats> for ContainerStats {
+    fn from(stats: &Stats) -> Self {
+        let cpu = &stats.cpu_stats;
+        let precpu = &stats.precpu_stats;
+        let cpu_delta = cpu
+            .cpu_usage
+            .total_usage
+            .saturating_sub(precpu.cpu_usage.total_usage);
+        let system_delta = cpu
+            .system_cpu_usage
+            .unwrap_or(0)
+            .saturating_sub(precpu.system_cpu_usage.unwrap_or(0));
+        let cpus = cpu
+            .online_cpus
+            .or_else(|| cpu.cpu_usage.percpu_usage.as_ref().map(|p| p.len() as u64))
+            .unwrap_or(1);
+        let cpu_percent = if system_delta > 0 {
+            cpu_delta as f64 / system_delta as f64 * cpus as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        let memory = &stats.memory_stats;
+        let cache = match memory.stats {
+            Some(MemoryStatsStats::V1(v1)) => v1.total_inactive_file,
+            Some(MemoryStatsStats::V2(v2)) => v2.inactive_file,
+            None => 0,
+        };
+        let memory_usage = memory.usage.unwrap_or(0).saturating_sub(cache);
+        let memory_limit = memory.limit.unwrap_or(0);
+        let memory_percent = if memory_limit > 0 {
+            memory_usage as f64 / memory_limit as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        let (network_rx, network_tx) = stats
+            .networks
+            .iter()
+            .flat_map(|n| n.values())
+            .fold((0, 0), |(rx, tx), n| (rx + n.rx_bytes, tx + n.tx_bytes));
+
+        let block_bytes = |op: &str| {
+            stats
+                .blkio_stats
+                .io_service_bytes_recursive
+                .iter()
+                .flatten()
+                .filter(|e| e.op.eq_ignore_ascii_case(op))
+                .map(|e| e.value)
+                .sum()
+        };
+
+        Self {
+            id: stats.id.clone(),
+            name: stats.name.trim_start_matches('/').to_owned(),
+            cpu_percent,
+            memory_usage,
+            memory_limit,
+            memory_percent,
+            network_rx,
+            network_tx,
+            block_read: block_bytes("read"),
+            block_write: block_bytes("write"),
+            pids: stats.pids_stats.current.unwrap_or(0),
+        }
+    }
+}
+
+/// Usage of the sampled containers together, relative to the cpus and memory of the host.
+/// Only covers the containers the user can see, so it differs from user to user.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerTotals {
+    pub containers: usize,
+    pub cpus: u64,
+    /// 100 when every core is fully used
+    pub cpu_percent: f64,
+    pub memory_usage: u64,
+    pub memory_total: u64,
+    pub memory_percent: f64,
+    pub network_rx: u64,
+    pub network_tx: u64,
+    pub block_read: u64,
+    pub block_write: u64,
+}
+
+impl ContainerTotals {
+    pub fn aggregate(stats: &[ContainerStats], cpus: u64, memory_total: u64) -> Self {
+        let memory_usage = stats.iter().map(|s| s.memory_usage).sum();
+        Self {
+            containers: stats.len(),
+            cpus,
+            cpu_percent: stats.iter().map(|s| s.cpu_percent).sum::<f64>() / cpus.max(1) as f64,
+            memory_usage,
+            memory_total,
+            memory_percent: if memory_total > 0 {
+                memory_usage as f64 / memory_total as f64 * 100.0
+            } else {
+                0.0
+            },
+            network_rx: stats.iter().map(|s| s.network_rx).sum(),
+            network_tx: stats.iter().map(|s| s.network_tx).sum(),
+            block_read: stats.iter().map(|s| s.block_read).sum(),
+            block_write: stats.iter().map(|s| s.block_write).sum(),
+        }
+    }
+}
+
+/// A single sample, docker waits for a second reading to compute the cpu usage.
+pub async fn sample(docker: &Docker, id: &str) -> anyhow::Result<ContainerStats> {
+    let stats = docker
+        .stats(
+            id,
+            Some(StatsOptions {
+                stream: false,
+                one_shot: false,
+            }),
+        )
+        .next()
+        .await
+        .ok_or_else(|| anyhow!("no stats for container {id}"))??;
+    Ok(ContainerStats::from(&stats))
+}
+
+/// Samples concurrently, containers that stopped in the meantime are left out.
+pub async fn sample_many(docker: &Docker, ids: &[String]) -> Vec<ContainerStats> {
+    join_all(ids.iter().map(|id| sample(docker, id)))
+        .await
+        .into_iter()
+        .filter_map(|s| {
+            s.map_err(|error| debug!(%error, "could not sample container"))
+                .ok()
+        })
+        .collect()
+}
+
+/// A sample of a running container with the labels that decide who may see it.
+#[derive(Debug, Clone)]
+pub struct LiveSample {
+    pub stats: ContainerStats,
+    pub labels: HashMap<String, String>,
+}
+
+/// Latest usage of every running container, sampled by a single task for all stats sockets.
+#[derive(Debug, Clone)]
+pub struct LiveStats {
+    latest: Arc<watch::Sender<Arc<Vec<LiveSample>>>>,
+}
+
+impl LiveStats {
+    pub fn new() -> Self {
+        // Receivers come from `subscribe`, the sender works without any
+        let (latest, _) = watch::channel(Arc::new(Vec::new()));
+        Self {
+            latest: Arc::new(latest),
+        }
+    }
+
+    /// Sees every sample taken from now on.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Vec<LiveSample>>> {
+        self.latest.subscribe()
+    }
+
+    /// Samples all running containers every `interval` while a socket listens, runs forever.
+    pub async fn run_sampler(self, docker: Docker, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if self.latest.receiver_count() == 0 {
+                continue;
+            }
+            let containers = match docker
+                .list_containers(None::<ListContainersOptions<String>>)
+                .await
+            {
+                Ok(c) => c,
+                Err(error) => {
+                    warn!(%error, "could not list containers for stats");
+                    continue;
+                }
+            };
+            let mut labels: HashMap<String, HashMap<String, String>> = containers
+                .into_iter()
+                .filter_map(|c| Some((c.id?, c.labels.unwrap_or_default())))
+                .collect();
+            let ids: Vec<String> = labels.keys().cloned().collect();
+            let samples = sample_many(&docker, &ids)
+                .await
+                .into_iter()
+                .map(|stats| LiveSample {
+                    labels: labels.remove(&stats.id).unwrap_or_default(),
+                    stats,
+                })
+                .collect();
+            self.latest.send_replace(Arc::new(samples));
+        }
+    }
+}
+
+impl Default for LiveStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
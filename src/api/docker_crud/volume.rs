@@ -1,30 +1,61 @@
 use axum::extract::Path;
 use axum::Json;
 use axum::{extract::State, http::StatusCode};
+use axum_extra::extract::Query;
 
 use bollard::volume::{ListVolumesOptions, PruneVolumesOptions, RemoveVolumeOptions};
+use bollard::Docker;
 use serde_json::json;
 
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
 use crate::SharedAppState;
 
+use super::listing::{total_count, ListKind, ListParams, ListReturn, SortKey, SortValue};
+use super::prune::{known_size, PruneFilters, PruneItem, PruneKind, PruneParams, PruneReport};
 use super::CrudReturn;
 
-pub async fn volumes(State(state): State<SharedAppState>) -> CrudReturn {
-    let ret = state
+/// Set by the daemon on volumes created without a name
+const ANONYMOUS_VOLUME_LABEL: &str = "com.docker.volume.anonymous";
+
+pub async fn volumes(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<ListParams>,
+) -> ListReturn {
+    let filters = params.docker_filters(ListKind::Volume)?;
+    let mut ret = state
         .docker
         .lock_owned()
         .await
-        .list_volumes(Some(ListVolumesOptions::<String> {
-            ..Default::default()
-        }))
+        .list_volumes(Some(ListVolumesOptions::<String> { filters }))
         .await?;
-    Ok((StatusCode::OK, Json(json!(&ret))))
+
+    let mut volumes = ret.volumes.take().unwrap_or_default();
+    volumes.retain(|v| user.can_see(Some(&v.labels)));
+    // Keeps the `{ Volumes, Warnings }` shape, only the volumes are paged
+    let (page, total) = params.page(volumes, |v, key| match key {
+        SortKey::Created => v.created_at.clone().into(),
+        SortKey::Name => Some(v.name.clone()).into(),
+        SortKey::Size => SortValue::Missing,
+    });
+    ret.volumes = Some(page);
+    Ok((StatusCode::OK, total_count(total), Json(json!(&ret))))
 }
 
 pub async fn remove_volume(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path(name): Path<String>,
 ) -> CrudReturn {
+    user.require_scope(Scope::ResourcesWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Volume,
+        &name,
+        Access::Manage,
+    )
+    .await?;
     state
         .docker
         .lock_owned()
@@ -34,12 +65,54 @@ pub async fn remove_volume(
     Ok((StatusCode::OK, Json(json!({}))))
 }
 
-pub async fn prune_volumes(State(state): State<SharedAppState>) -> CrudReturn {
-    state
-        .docker
-        .lock_owned()
-        .await
-        .prune_volumes(None::<PruneVolumesOptions<String>>)
+pub async fn prune_volumes(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<PruneParams>,
+) -> CrudReturn {
+    user.require_scope(Scope::ResourcesWrite)?;
+    user.require_global()?;
+    let filters = params.filters(PruneKind::Volume)?;
+    let docker = state.docker.lock_owned().await;
+
+    let plan = plan_volume_prune(&docker, &filters).await?;
+    if params.dry_run {
+        return Ok((StatusCode::OK, Json(json!(PruneReport::planned(plan)))));
+    }
+
+    let ret = docker
+        .prune_volumes(Some(PruneVolumesOptions {
+            filters: filters.docker_filters(PruneKind::Volume),
+        }))
         .await?;
-    Ok((StatusCode::OK, Json(json!({}))))
+    let report = PruneReport::executed(
+        plan,
+        &ret.volumes_deleted.unwrap_or_default(),
+        ret.space_reclaimed.and_then(known_size),
+    );
+    Ok((StatusCode::OK, Json(json!(report))))
+}
+
+/// Same selection as the daemon (API >= 1.42): volumes that no container uses,
+/// only anonymous ones unless `all` is set.
+async fn plan_volume_prune(
+    docker: &Docker,
+    filters: &PruneFilters,
+) -> anyhow::Result<Vec<PruneItem>> {
+    let usage = docker.df().await?;
+    Ok(usage
+        .volumes
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|v| filters.all || v.labels.contains_key(ANONYMOUS_VOLUME_LABEL))
+        .filter(|v| filters.matches_labels(Some(&v.labels)))
+        .filter_map(|v| {
+            let usage = v.usage_data?;
+            (usage.ref_count == 0).then(|| PruneItem {
+                id: v.name,
+                name: None,
+                size: known_size(usage.size),
+            })
+        })
+        .collect())
 }
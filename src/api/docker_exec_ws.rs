@@ -0,0 +1,286 @@
+use std::{borrow::Cow, net::SocketAddr, time::Duration};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{
+        connect_info::ConnectInfo,
+        ws::{close_code, CloseFrame, Message as WsMessage, WebSocket},
+        Path, Query, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::IntoResponse,
+};
+use bollard::{
+    exec::{CreateExecOptions, ResizeExecOptions, StartExecOptions, StartExecResults},
+    Docker,
+};
+use futures::{sink::SinkExt, stream::StreamExt};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, trace, warn};
+use ulid::Ulid;
+
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::{CurrentUser, Scope};
+use crate::api::docker_crud::exec::exec_output;
+use crate::api::error::ApiError;
+use crate::SharedAppState;
+
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// Set in the environment of every shell, its processes and their children inherit it.
+const SHELL_MARKER: &str = "SERVCUR_SHELL";
+/// Kills every process of the container that carries the marker given as `$1` and prints their pids.
+/// Processes of other users are left alone, the script runs as the user of the shell.
+const KILL_SCRIPT: &str = r#"for p in /proc/[0-9]*; do
+  tr '\0' '\n' 2>/dev/null < "$p/environ" | grep -qx "$1" && kill -KILL "${p#/proc/}" 2>/dev/null && echo "${p#/proc/}"
+done"#;
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+pub struct ShellParams {
+    /// Program to run, `/bin/sh` when not set
+    shell: Option<String>,
+    user: Option<String>,
+    workdir: Option<String>,
+    /// Initial terminal size
+    cols: Option<u16>,
+    rows: Option<u16>,
+}
+
+/// Text frames sent by the client, binary frames are written to stdin as is.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ShellMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+}
+
+pub async fn ws_upgrader(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<ShellParams>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<impl IntoResponse, ApiError> {
+    user.require_scope(Scope::ContainersWrite)?;
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &id,
+        Access::Deploy,
+    )
+    .await?;
+
+    let shell = params.shell.unwrap_or_else(|| DEFAULT_SHELL.to_owned());
+    if shell.trim().is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("shell can not be empty"),
+        ));
+    }
+
+    let docker = state.docker.lock_owned().await.clone();
+    let marker = format!("{SHELL_MARKER}={}", Ulid::new());
+    let exec = docker
+        .create_exec(
+            &id,
+            CreateExecOptions {
+                attach_stdin: Some(true),
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                tty: Some(true),
+                env: Some(vec!["TERM=xterm-256color".to_owned(), marker.clone()]),
+                cmd: Some(vec![shell.clone()]),
+                user: params.user.clone(),
+                working_dir: params.workdir,
+                ..Default::default()
+            },
+        )
+        .await?;
+    info!(
+        container = id,
+        exec = exec.id,
+        shell,
+        username = user.username,
+        "opening shell"
+    );
+
+    let size = params.cols.zip(params.rows);
+    trace!(address = %addr, "upgrading connection");
+    let shell = Shell {
+        exec_id: exec.id,
+        container: id,
+        user: params.user,
+        marker,
+    };
+    Ok(ws.on_upgrade(move |socket| handle_socket(docker, shell, size, socket, addr)))
+}
+
+/// What is needed to find the processes of a shell again once the socket closes.
+struct Shell {
+    exec_id: String,
+    container: String,
+    user: Option<String>,
+    marker: String,
+}
+
+async fn handle_socket(
+    docker: Docker,
+    shell: Shell,
+    size: Option<(u16, u16)>,
+    mut socket: WebSocket,
+    address: SocketAddr,
+) {
+    let exec_id = &shell.exec_id;
+    let started = docker
+        .start_exec(
+            exec_id,
+            Some(StartExecOptions {
+                detach: false,
+                ..Default::default()
+            }),
+        )
+        .await;
+    let (mut output, mut input) = match started {
+        Ok(StartExecResults::Attached { output, input }) => (output, input),
+        Ok(StartExecResults::Detached) => return,
+        Err(error) => {
+            error!(%address, %error, "could not start shell");
+            let _ = socket
+                .send(WsMessage::Close(Some(CloseFrame {
+                    code: close_code::ERROR,
+                    reason: Cow::from("could not start shell"),
+                })))
+                .await;
+            return;
+        }
+    };
+    if let Some((cols, rows)) = size {
+        resize(&docker, exec_id, cols, rows).await;
+    }
+
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    loop {
+        tokio::select! {
+            out = output.next() => match out {
+                Some(Ok(out)) => {
+                    if let Err(error) = ws_tx.send(WsMessage::Binary(out.into_bytes().to_vec())).await {
+                        warn!(%address, %error, "ws sending err; closing shell");
+                        break;
+                    }
+                }
+                Some(Err(error)) => {
+                    error!(%address, %error, "shell output err; closing ws");
+                    let _ = ws_tx
+                        .send(WsMessage::Close(Some(CloseFrame {
+                            code: close_code::ERROR,
+                            reason: Cow::from("shell error"),
+                        })))
+                        .await;
+                    break;
+                }
+                // The shell exited
+                None => {
+                    let _ = ws_tx
+                        .send(WsMessage::Close(Some(CloseFrame {
+                            code: close_code::NORMAL,
+                            reason: Cow::from("shell exited"),
+                        })))
+                        .await;
+                    break;
+                }
+            },
+            msg = ws_rx.next() => match msg {
+                Some(Ok(WsMessage::Binary(data))) => {
+                    if input.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(WsMessage::Text(text))) => match serde_json::from_str::<ShellMessage>(&text) {
+                    Ok(ShellMessage::Input { data }) => {
+                        if input.write_all(data.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(ShellMessage::Resize { cols, rows }) => resize(&docker, exec_id, cols, rows).await,
+                    Err(error) => warn!(%address, %error, "invalid shell message"),
+                },
+                Some(Ok(WsMessage::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(error)) => {
+                    warn!(%address, %error, "ws receiving err; closing shell");
+                    break;
+                }
+            }
+        }
+    }
+
+    // Closing stdin hangs up the terminal, which ends the shell and with it the exec instance
+    let _ = input.shutdown().await;
+    drop(input);
+    drop(output);
+    wait_for_exit(&docker, &shell).await;
+}
+
+async fn resize(docker: &Docker, exec_id: &str, cols: u16, rows: u16) {
+    if let Err(error) = docker
+        .resize_exec(
+            exec_id,
+            ResizeExecOptions {
+                height: rows,
+                width: cols,
+            },
+        )
+        .await
+    {
+        warn!(%error, exec = exec_id, "could not resize shell");
+    }
+}
+
+/// Processes that ignore the hangup, such as `top` or a `nohup` job, are killed once the grace period is over.
+async fn wait_for_exit(docker: &Docker, shell: &Shell) {
+    for _ in 0..10 {
+        match docker.inspect_exec(&shell.exec_id).await {
+            Ok(exec) if exec.running == Some(true) => {
+                tokio::time::sleep(Duration::from_millis(200)).await
+            }
+            // Exec instances without a running process are removed by the daemon
+            _ => return,
+        }
+    }
+
+    match kill_shell(docker, shell).await {
+        Ok(pids) => info!(
+            exec = shell.exec_id,
+            ?pids,
+            "killed shell that kept running after the socket closed"
+        ),
+        Err(error) => error!(
+            exec = shell.exec_id,
+            %error,
+            "shell is still running after the socket closed and could not be killed"
+        ),
+    }
+}
+
+/// The pid docker reports is the one of the host, which servcur does not see from its own container.
+/// A second exec in the container finds the processes by the marker of the shell instead.
+async fn kill_shell(docker: &Docker, shell: &Shell) -> anyhow::Result<Vec<String>> {
+    let cmd = ["sh", "-c", KILL_SCRIPT, "sh", &shell.marker]
+        .map(str::to_owned)
+        .to_vec();
+    let (_, pids) = exec_output(
+        docker,
+        &shell.container,
+        cmd,
+        shell.user.clone(),
+        KILL_TIMEOUT,
+    )
+    .await?;
+    if pids.is_empty() {
+        return Err(anyhow!("no process of the shell could be killed"));
+    }
+    Ok(pids)
+}
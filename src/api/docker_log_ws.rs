@@ -22,6 +22,9 @@ use futures::stream::StreamExt;
 
 use tracing::{error, trace, warn};
 
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::CurrentUser;
+use crate::api::error::ApiError;
 use crate::SharedAppState;
 
 #[derive(Deserialize, Debug)]
@@ -31,11 +34,19 @@ pub struct LogPage {
 
 pub async fn ws_upgrader(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Query(query): Query<LogPage>,
     Path(id): Path<String>,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> Result<impl IntoResponse, impl IntoResponse> {
+) -> Result<impl IntoResponse, ApiError> {
+    user.require_resource(
+        &*state.docker.lock().await,
+        ResourceKind::Container,
+        &id,
+        Access::View,
+    )
+    .await?;
     let stream;
     {
         let since = query.since.unwrap();
@@ -55,9 +66,7 @@ pub async fn ws_upgrader(
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
     trace!(address = %addr, "upgrading connection");
-    Ok::<hyper::Response<axum::body::Body>, ()>(
-        ws.on_upgrade(move |socket| handle_socket(stream, socket, addr)),
-    )
+    Ok(ws.on_upgrade(move |socket| handle_socket(stream, socket, addr)))
 }
 
 pub async fn handle_socket(
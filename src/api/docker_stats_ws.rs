@@ -0,0 +1,191 @@
+use std::{borrow::Cow, net::SocketAddr, time::Duration};
+
+use axum::{
+    extract::{
+        connect_info::ConnectInfo,
+        ws::{close_code, CloseFrame, Message as WsMessage, WebSocket},
+        Path, State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+};
+use axum_extra::extract::Query;
+use bollard::Docker;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::time::Instant;
+use tracing::{error, trace, warn};
+
+use crate::api::auth::access::{Access, ResourceKind};
+use crate::api::auth::CurrentUser;
+use crate::api::docker_crud::stats::{ContainerStats, ContainerTotals, LiveSample, LiveStats};
+use crate::api::error::ApiError;
+use crate::SharedAppState;
+
+#[derive(Debug, Deserialize)]
+pub struct StatsParams {
+    /// Containers to follow, can be repeated. All running containers the user can see when empty
+    #[serde(default)]
+    id: Vec<String>,
+    /// Seconds between messages, never shorter than the configured stats interval
+    interval: Option<u64>,
+}
+
+#[derive(Debug)]
+enum Target {
+    Containers(Vec<String>),
+    /// Running containers visible to the user
+    Visible(CurrentUser),
+    /// Visible containers and their sum compared to the host
+    Host(CurrentUser),
+}
+
+impl Target {
+    fn selects(&self, sample: &LiveSample) -> bool {
+        match self {
+            // Followed by id, short id or name
+            Target::Containers(ids) => ids
+                .iter()
+                .any(|id| sample.stats.id.starts_with(id.as_str()) || sample.stats.name == *id),
+            Target::Visible(user) | Target::Host(user) => user.can_see(Some(&sample.labels)),
+        }
+    }
+}
+
+/// `GET /containers/:id/stats`
+pub async fn container_ws_upgrader(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<StatsParams>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<impl IntoResponse, ApiError> {
+    let params = StatsParams {
+        id: vec![id],
+        ..params
+    };
+    containers_ws_upgrader(State(state), user, Query(params), ws, ConnectInfo(addr)).await
+}
+
+/// `GET /containers/stats`
+pub async fn containers_ws_upgrader(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<StatsParams>,
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<impl IntoResponse, ApiError> {
+    for id in &params.id {
+        user.require_resource(
+            &*state.docker.lock().await,
+            ResourceKind::Container,
+            id,
+            Access::View,
+        )
+        .await?;
+    }
+    let target = if params.id.is_empty() {
+        Target::Visible(user)
+    } else {
+        Target::Containers(params.id)
+    };
+
+    let interval = interval(&state, params.interval);
+    let docker = state.docker.lock_owned().await.clone();
+    let live = state.live_stats.clone();
+    trace!(address = %addr, "upgrading connection");
+    Ok(ws.on_upgrade(move |socket| handle_socket(docker, live, target, interval, socket, addr)))
+}
+
+/// `GET /system/stats`
+pub async fn host_ws_upgrader(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Query(params): Query<StatsParams>,
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<impl IntoResponse, ApiError> {
+    let interval = interval(&state, params.interval);
+    let docker = state.docker.lock_owned().await.clone();
+    let live = state.live_stats.clone();
+    trace!(address = %addr, "upgrading connection");
+    Ok(ws.on_upgrade(move |socket| {
+        handle_socket(docker, live, Target::Host(user), interval, socket, addr)
+    }))
+}
+
+fn interval(state: &SharedAppState, requested: Option<u64>) -> Duration {
+    requested
+        .map(Duration::from_secs)
+        .unwrap_or(state.settings.stats_interval)
+        .max(state.settings.stats_interval)
+}
+
+/// Sends the samples of the shared sampler, so every socket costs no more docker calls than one.
+async fn handle_socket(
+    docker: Docker,
+    live: LiveStats,
+    target: Target,
+    interval: Duration,
+    mut socket: WebSocket,
+    address: SocketAddr,
+) {
+    let (cpus, memory_total) = match (&target, docker.info().await) {
+        (Target::Host(_), Ok(info)) => (
+            info.ncpu.unwrap_or(1) as u64,
+            info.mem_total.unwrap_or(0) as u64,
+        ),
+        (Target::Host(_), Err(error)) => {
+            error!(%address, %error, "could not read host info; closing ws");
+            let _ = socket
+                .send(WsMessage::Close(Some(CloseFrame {
+                    code: close_code::ERROR,
+                    reason: Cow::from("docker error"),
+                })))
+                .await;
+            return;
+        }
+        _ => (1, 0),
+    };
+
+    let mut latest = live.subscribe();
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            changed = latest.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                if last_sent.is_some_and(|t| t.elapsed() < interval) {
+                    continue;
+                }
+                let samples = latest.borrow_and_update().clone();
+                let containers: Vec<ContainerStats> = samples
+                    .iter()
+                    .filter(|s| target.selects(s))
+                    .map(|s| s.stats.clone())
+                    .collect();
+                let message = match &target {
+                    Target::Host(_) => json!({
+                        "totals": ContainerTotals::aggregate(&containers, cpus, memory_total),
+                        "containers": containers,
+                    }),
+                    _ => json!({ "containers": containers }),
+                };
+                if let Err(error) = socket.send(WsMessage::Text(message.to_string())).await {
+                    warn!(%address, %error, "ws sending err; closing stats");
+                    break;
+                }
+                last_sent = Some(Instant::now());
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(WsMessage::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(error)) => {
+                    warn!(%address, %error, "ws receiving err; closing stats");
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,11 @@
+pub mod audit;
+pub mod auth;
 pub mod docker_crud;
+pub mod docker_exec_ws;
 pub mod docker_log_ws;
+pub mod docker_stats_ws;
 pub mod error;
+pub mod origin;
 pub mod projects;
+pub mod rate_limit;
+pub mod stats_history;
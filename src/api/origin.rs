@@ -0,0 +1,93 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE, HOST, ORIGIN, UPGRADE},
+        HeaderMap, HeaderValue, Method, StatusCode, Uri,
+    },
+    middleware::Next,
+    response::Response,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::warn;
+
+use crate::{config::Settings, SharedAppState};
+
+use super::{docker_crud::listing::TOTAL_COUNT_HEADER, error::ApiError};
+
+/// Only the configured origins get CORS headers, without any the API is same-origin only.
+pub fn cors_layer(settings: &Settings) -> Option<CorsLayer> {
+    if settings.allowed_origins.is_empty() {
+        return None;
+    }
+    let origins = settings
+        .allowed_origins
+        .iter()
+        .filter_map(|o| HeaderValue::from_str(o).ok())
+        .collect::<Vec<_>>();
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::DELETE,
+                Method::PATCH,
+            ])
+            .allow_headers([AUTHORIZATION, CONTENT_TYPE])
+            .expose_headers([TOTAL_COUNT_HEADER]),
+    )
+}
+
+/// Browsers don't apply CORS to WebSocket upgrades or simple form posts,
+/// so those are refused here when they come from a foreign origin.
+pub async fn check_origin(
+    State(state): State<SharedAppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let is_upgrade = req.headers().contains_key(UPGRADE);
+    let is_mutation = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if !is_upgrade && !is_mutation {
+        return Ok(next.run(req).await);
+    }
+
+    // Non browser clients (curl, git hosts calling webhooks) don't send an origin
+    let Some(origin) = req.headers().get(ORIGIN).and_then(|o| o.to_str().ok()) else {
+        return Ok(next.run(req).await);
+    };
+
+    if !is_same_origin(origin, req.headers())
+        && !state.settings.allowed_origins.iter().any(|o| o == origin)
+    {
+        warn!(
+            origin,
+            path = req.uri().path(),
+            "refused request from foreign origin"
+        );
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            anyhow!("origin {origin} is not allowed"),
+        ));
+    }
+    Ok(next.run(req).await)
+}
+
+fn is_same_origin(origin: &str, headers: &HeaderMap) -> bool {
+    let Some(origin_host) = origin
+        .parse::<Uri>()
+        .ok()
+        .and_then(|u| u.authority().map(|a| a.to_string()))
+    else {
+        return false;
+    };
+
+    // Behind a reverse proxy the original host is forwarded
+    [headers.get("x-forwarded-host"), headers.get(HOST)]
+        .into_iter()
+        .flatten()
+        .filter_map(|h| h.to_str().ok())
+        .any(|host| host.eq_ignore_ascii_case(&origin_host))
+}
@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 
 use crate::api::projects::{executor::ProjectIoHandle, BaseProject};
+use crate::config::{BRANCH_LABEL, PROJECT_LABEL};
 
 use super::Action;
 
@@ -17,6 +18,26 @@ pub enum DockerFileActions {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DockerFile {
     image_version: usize,
+    /// Repository prefix every build is pushed to, e.g. `registry.example.com/team`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    push_to: Option<String>,
+}
+
+impl DockerFile {
+    pub fn set_push_to(&mut self, push_to: Option<String>) {
+        self.push_to = push_to;
+    }
+
+    pub fn push_to(&self) -> Option<&str> {
+        self.push_to.as_deref()
+    }
+
+    /// The image of the latest build and the name it is pushed as
+    pub fn push_target(&self, project: &BaseProject) -> Option<(String, String)> {
+        let push_to = self.push_to.as_ref()?;
+        let image = format!("{}-{}:{}", project.name, project.branch, self.image_version);
+        Some((image.clone(), format!("{push_to}/{image}")))
+    }
 }
 
 impl Action for DockerFile {
@@ -34,6 +55,10 @@ impl Action for DockerFile {
                 build_command
                     .arg("build")
                     .arg(".")
+                    .arg("--label")
+                    .arg(format!("{PROJECT_LABEL}={}", project.name))
+                    .arg("--label")
+                    .arg(format!("{BRANCH_LABEL}={}", project.branch))
                     .arg("-t")
                     .arg(format!(
                         "{}-{}:{}",
@@ -49,6 +74,10 @@ impl Action for DockerFile {
                 start_command
                     .arg("run")
                     .arg("-d")
+                    .arg("--label")
+                    .arg(format!("{PROJECT_LABEL}={}", project.name))
+                    .arg("--label")
+                    .arg(format!("{BRANCH_LABEL}={}", project.branch))
                     .arg("--name")
                     .arg(format!(
                         "{}-{}-{}",
@@ -38,6 +38,13 @@ pub struct ActionCommand {
 }
 
 impl ActionCommand {
+    pub fn is_build(&self) -> bool {
+        matches!(
+            self.action_kind,
+            ProjectAction::DockerFile(DockerFileActions::Build)
+        )
+    }
+
     pub async fn try_exec(
         self,
         dir: &FsPath,
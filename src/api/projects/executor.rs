@@ -1,14 +1,17 @@
 use std::{
     collections::BTreeMap,
+    fmt,
     path::{Path, PathBuf},
+    pin::Pin,
     process::{Output, Stdio},
     sync::Arc,
 };
 
 use serde::{Deserialize, Serialize};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_recursion::async_recursion;
+use futures::{Stream, StreamExt};
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::Command,
@@ -75,11 +78,37 @@ impl IoLog {
     }
 }
 
+/// Output of a job that does not run as a local process.
+#[derive(Debug)]
+pub enum IoEvent {
+    Stdout(String),
+    Stderr(String),
+    Exit(usize),
+}
+
+pub type IoStream = Pin<Box<dyn Stream<Item = IoEvent> + Send>>;
+
+pub enum IoSource {
+    Command(Command),
+    /// Output produced elsewhere, e.g. by the docker daemon.
+    /// A stream that ends without an exit status counts as failed.
+    Stream(IoStream),
+}
+
+impl fmt::Debug for IoSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Command(command) => f.debug_tuple("Command").field(command).finish(),
+            Self::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ProjectIoHandle {
     pub project: BaseProject,
     pub tag: Option<String>,
-    pub command: Command,
+    pub source: IoSource,
     pub depends_on: Option<Box<ProjectIoHandle>>,
 }
 
@@ -87,7 +116,16 @@ impl ProjectIoHandle {
     pub fn new(project: BaseProject, command: Command) -> Self {
         Self {
             project,
-            command,
+            source: IoSource::Command(command),
+            tag: None,
+            depends_on: None,
+        }
+    }
+
+    pub fn from_stream(project: BaseProject, stream: IoStream) -> Self {
+        Self {
+            project,
+            source: IoSource::Stream(stream),
             tag: None,
             depends_on: None,
         }
@@ -210,7 +248,8 @@ impl ProjectIoExecutor {
         self.exec_tx
             .send((id, handle))
             .await
-            .context("could not send handle to executor")?;
+            // The handle is not Sync, so the error can not carry it
+            .map_err(|_| anyhow!("could not send handle to executor"))?;
         Ok(id)
     }
 
@@ -227,16 +266,45 @@ impl ProjectIoExecutor {
 
 #[async_recursion]
 async fn execute_handle(
-    mut handle: ProjectIoHandle,
+    handle: ProjectIoHandle,
     output_handle: OutputSendHandle,
 ) -> Result<Box<IoLog>> {
     let mut child = None;
     if let Some(child_handle) = handle.depends_on {
-        child = Some(execute_handle(*child_handle, output_handle.clone()).await?);
+        let log = execute_handle(*child_handle, output_handle.clone()).await?;
+        // Nothing to continue with when the job it depends on failed
+        if log.status != 0 {
+            let skipped = IoLog::new(
+                log.status,
+                handle.project,
+                handle.tag,
+                String::new(),
+                "skipped, the job it depends on failed\n".to_owned(),
+            );
+            return Ok(Box::new(skipped.set_child(log)));
+        }
+        child = Some(log);
+    }
+
+    let (status, stdout, stderr) = match handle.source {
+        IoSource::Command(command) => execute_command(command, output_handle).await?,
+        IoSource::Stream(stream) => execute_stream(stream, output_handle).await,
+    };
+
+    let mut io = IoLog::new(status, handle.project, handle.tag, stdout, stderr);
+
+    if let Some(v) = child {
+        io = io.set_child(v);
     }
+    Ok(Box::new(io))
+}
 
-    let mut command_handle = handle
-        .command
+/// Returns the exit status, stdout and stderr
+async fn execute_command(
+    mut command: Command,
+    output_handle: OutputSendHandle,
+) -> Result<(usize, String, String)> {
+    let mut command_handle = command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -308,18 +376,35 @@ async fn execute_handle(
         };
     }
 
-    let mut io = IoLog::new(
-        out.status.code().unwrap_or(0) as usize,
-        handle.project,
-        handle.tag,
-        stdout,
-        stderr,
-    );
+    Ok((out.status.code().unwrap_or(0) as usize, stdout, stderr))
+}
 
-    if let Some(v) = child {
-        io = io.set_child(v);
+/// Returns the exit status, stdout and stderr
+async fn execute_stream(
+    mut stream: IoStream,
+    output_handle: OutputSendHandle,
+) -> (usize, String, String) {
+    let mut status = 1;
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+
+    while let Some(event) = stream.next().await {
+        let (total, sender, line) = match event {
+            IoEvent::Stdout(l) => (&mut stdout, &output_handle.stdout, l),
+            IoEvent::Stderr(l) => (&mut stderr, &output_handle.stderr, l),
+            IoEvent::Exit(s) => {
+                status = s;
+                continue;
+            }
+        };
+        total.push_str(&line);
+        total.push('\n');
+        if let Err(e) = sender.send(line) {
+            trace!(?e, "output sending error");
+        }
     }
-    Ok(Box::new(io))
+
+    (status, stdout, stderr)
 }
 
 #[instrument(err(Debug), name = "IoHandleExecute", level = "info")]
@@ -22,7 +22,14 @@ use futures::{sink::SinkExt, stream::StreamExt};
 
 use tracing::{debug, error, info, warn};
 
-use crate::{api::error::ApiError, util::wait_for_ws_close, SharedAppState};
+use crate::{
+    api::{
+        auth::{access::Access, CurrentUser},
+        error::ApiError,
+    },
+    util::wait_for_ws_close,
+    SharedAppState,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SubscribeKind {
@@ -34,6 +41,7 @@ pub enum SubscribeKind {
 
 pub async fn ws_upgrader(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Path((id, kind)): Path<(Ulid, SubscribeKind)>,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -46,6 +54,7 @@ pub async fn ws_upgrader(
             StatusCode::NOT_FOUND,
             anyhow::Error::msg("could not find handle w/ that id"),
         ))?;
+    user.require_project(&handle.1.name, Some(&handle.1.branch), Access::View)?;
 
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
 
 use anyhow::{bail, Result};
@@ -5,7 +7,7 @@ use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 use self::actions::ProjectKind;
-use crate::config::PROJECT_FOLDER;
+use crate::config::{BRANCH_LABEL, PROJECT_FOLDER, PROJECT_LABEL};
 
 pub mod actions;
 pub mod executor;
@@ -23,10 +25,11 @@ pub struct NewProject {
     project_kind: ProjectKind,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum GitAuth {
     None,
+    /// `username:password` or a bare access token
     Token(String),
 }
 
@@ -34,6 +37,50 @@ impl GitAuth {
     pub fn is_none(&self) -> bool {
         matches!(self, GitAuth::None)
     }
+
+    pub fn credential(&self) -> Option<GitCredential> {
+        match self {
+            GitAuth::None => None,
+            GitAuth::Token(t) => Some(GitCredential::from_userinfo(t)),
+        }
+    }
+}
+
+// Never print the token
+impl fmt::Debug for GitAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitAuth::None => write!(f, "None"),
+            GitAuth::Token(_) => write!(f, "Token(<redacted>)"),
+        }
+    }
+}
+
+/// Git HTTPS credentials, kept in the secret store and handed to git through askpass.
+#[derive(Serialize, Deserialize)]
+pub struct GitCredential {
+    pub username: String,
+    pub password: String,
+}
+
+impl GitCredential {
+    /// Parses `username:password`, a bare token is used as username.
+    pub fn from_userinfo(userinfo: &str) -> Self {
+        match userinfo.split_once(':') {
+            Some((username, password)) => Self {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            },
+            None => Self {
+                username: userinfo.to_owned(),
+                password: String::new(),
+            },
+        }
+    }
+}
+
+pub fn git_secret_key(name: &str, branch: &str) -> String {
+    format!("git/{name}/{branch}")
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -84,8 +131,67 @@ pub struct Project {
     project_kind: ProjectKind,
 }
 
+impl Project {
+    pub fn set_push_to(&mut self, push_to: Option<String>) -> Result<()> {
+        match &mut self.project_kind {
+            ProjectKind::DockerFile(d) => d.set_push_to(push_to),
+            ProjectKind::DockerCompose(_) => bail!("only dockerfile projects build images"),
+        }
+        Ok(())
+    }
+
+    /// Repository prefix the builds are pushed to
+    pub fn push_to(&self) -> Option<&str> {
+        match &self.project_kind {
+            ProjectKind::DockerFile(d) => d.push_to(),
+            ProjectKind::DockerCompose(_) => None,
+        }
+    }
+
+    /// Local image of the latest build and where it goes, when the project pushes its builds
+    pub fn push_target(&self) -> Option<(String, String)> {
+        match &self.project_kind {
+            ProjectKind::DockerFile(d) => d.push_target(&BaseProject::new(
+                self.project_name.clone(),
+                self.branch.clone(),
+            )),
+            ProjectKind::DockerCompose(_) => None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct BaseProject {
     name: String,
     branch: String,
 }
+
+impl BaseProject {
+    pub fn new(name: String, branch: String) -> Self {
+        Self { name, branch }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn branch(&self) -> &str {
+        &self.branch
+    }
+
+    /// Owner of a job on a docker resource, taken from its ownership labels.
+    /// Unowned resources get `fallback` as name, so only global users see their jobs.
+    pub fn from_labels(labels: Option<&HashMap<String, String>>, fallback: &str) -> Self {
+        let label = |key| labels.and_then(|l| l.get(key)).cloned();
+        match label(PROJECT_LABEL) {
+            Some(name) => Self {
+                name,
+                branch: label(BRANCH_LABEL).unwrap_or_default(),
+            },
+            None => Self {
+                name: fallback.to_owned(),
+                branch: String::new(),
+            },
+        }
+    }
+}
@@ -2,16 +2,20 @@ use std::path::PathBuf;
 use std::process::Stdio;
 
 use tokio::fs;
-use tracing::warn;
+use tracing::{info, warn};
 
-use crate::util::create_git_auth_url;
+use crate::secrets::SecretStore;
 use crate::util::format_project_folder;
 use crate::util::format_project_root_folder;
+use crate::util::git_command;
 
+use super::git_secret_key;
+use super::project_store::ProjectStore;
+use super::GitCredential;
 use super::NewProject;
 use super::PROJECT_FOLDER;
 
-pub async fn new_project(project: &NewProject) -> anyhow::Result<PathBuf> {
+pub async fn new_project(project: &NewProject, secrets: &SecretStore) -> anyhow::Result<PathBuf> {
     // Name invalid
     if project.name.contains('/') || project.name.contains('\\') {
         return Err(anyhow::Error::msg("invalid project name"));
@@ -29,16 +33,23 @@ pub async fn new_project(project: &NewProject) -> anyhow::Result<PathBuf> {
         return Err(anyhow::Error::msg("Project/ branch already exists"));
     }
 
+    // Credentials are kept encrypted, never in the remote url
+    let credential = project.auth.credential();
+    let secret_key = git_secret_key(&project.name, &project.branch);
+    if let Some(credential) = &credential {
+        secrets.set(&secret_key, credential).await?;
+    }
+
     // Create folder with projectname
     tokio::fs::create_dir_all(&project_root_folder).await?;
 
     // Create folder with branch name
     tokio::fs::create_dir_all(&project_branch_folder).await?;
 
-    // Clone git repo (with a insecure remote, :0 )
-    let output = tokio::process::Command::new("git")
+    let output = git_command(project_branch_folder.as_ref(), credential.as_ref())
+        .await?
         .arg("clone")
-        .arg(&create_git_auth_url(&project.https_url, &project.auth))
+        .arg(&project.https_url)
         .arg("-b")
         .arg(&project.branch)
         // Makes it so that it doesn't create a folder within the current work-dir
@@ -50,13 +61,17 @@ pub async fn new_project(project: &NewProject) -> anyhow::Result<PathBuf> {
         .status()
         .await?;
     if !output.success() {
+        if let Err(e) = secrets.remove(&secret_key).await {
+            warn!(?e, "could not remove credentials of failed clone");
+        }
         return Err(anyhow::anyhow!(output.to_string()));
     }
     Ok(PathBuf::from(project_branch_folder))
 }
 
-pub async fn remove_project(name: &str, branch: &str) -> anyhow::Result<()> {
+pub async fn remove_project(name: &str, branch: &str, secrets: &SecretStore) -> anyhow::Result<()> {
     fs::remove_dir_all(format_project_folder(name, branch)).await?;
+    secrets.remove(&git_secret_key(name, branch)).await?;
     if let Err(e) = fs::remove_dir(format_project_root_folder(name)).await {
         warn!(e = %e, "project root directory was probably not empty");
     }
@@ -64,7 +79,7 @@ pub async fn remove_project(name: &str, branch: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn pull_project(name: &str, branch: &str) -> anyhow::Result<()> {
+pub async fn pull_project(name: &str, branch: &str, secrets: &SecretStore) -> anyhow::Result<()> {
     // Name invalid
     if name.contains('/') || name.contains('\\') {
         return Err(anyhow::Error::msg("Invalid project name"));
@@ -76,10 +91,12 @@ pub async fn pull_project(name: &str, branch: &str) -> anyhow::Result<()> {
         return Err(anyhow::Error::msg("Project/ branch doesn't exist"));
     }
 
+    let credential: Option<GitCredential> = secrets.get(&git_secret_key(name, branch)).await?;
+
     // Fetch git repo
-    let output = tokio::process::Command::new("git")
+    let output = git_command(project_branch_folder.as_ref(), credential.as_ref())
+        .await?
         .arg("pull")
-        .current_dir(project_branch_folder)
         .stdin(Stdio::null())
         .stderr(Stdio::null())
         .stdout(Stdio::null())
@@ -90,3 +107,62 @@ pub async fn pull_project(name: &str, branch: &str) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// Projects cloned by older versions have their token embedded in the `origin` url.
+/// Moves those credentials into the secret store and rewrites the remote without them.
+pub async fn migrate_remote_credentials(
+    projects: &ProjectStore,
+    secrets: &SecretStore,
+) -> anyhow::Result<()> {
+    for project in projects.get_all().await.0 {
+        let output = git_command(&project.path, None)
+            .await?
+            .args(["remote", "get-url", "origin"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await?;
+        if !output.status.success() {
+            continue;
+        }
+
+        let url = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        let Some((userinfo, rest)) = url
+            .strip_prefix("https://")
+            .and_then(|u| u.split_once('@'))
+            .filter(|(userinfo, _)| !userinfo.contains('/'))
+        else {
+            continue;
+        };
+
+        let credential = GitCredential::from_userinfo(userinfo);
+        secrets
+            .set(
+                &git_secret_key(&project.project_name, &project.branch),
+                &credential,
+            )
+            .await?;
+
+        let status = git_command(&project.path, None)
+            .await?
+            .args(["remote", "set-url", "origin", &format!("https://{rest}")])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .await?;
+        if !status.success() {
+            anyhow::bail!(
+                "could not rewrite remote of {}/{}",
+                project.project_name,
+                project.branch
+            );
+        }
+        info!(
+            project = project.project_name,
+            branch = project.branch,
+            "moved git credentials out of remote url"
+        );
+    }
+    Ok(())
+}
@@ -4,7 +4,7 @@ use tokio::sync::{Mutex, RwLock, RwLockWriteGuard};
 use crate::store::Store;
 
 use super::{BaseProject, Project, Projects};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 #[derive(Debug, Clone)]
 pub struct ProjectStore {
@@ -40,6 +40,23 @@ impl ProjectStore {
         Ok(())
     }
 
+    /// Changes a project and saves the result
+    pub async fn update(
+        &self,
+        name: &str,
+        branch: &str,
+        f: impl FnOnce(&mut Project) -> Result<()>,
+    ) -> Result<()> {
+        let mut store = self.inner.write().await;
+
+        let project = store
+            .get_mut(name, branch)
+            .ok_or_else(|| anyhow!("project does not exist"))?;
+        f(project)?;
+        self.fs_store.lock().await.write(&store.0).await?;
+        Ok(())
+    }
+
     pub async fn remove(&self, project: &BaseProject) -> Result<()> {
         let mut store = self.inner.write().await;
 
@@ -3,6 +3,8 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use axum::extract::{Path, State};
+use axum::http::header;
+use axum::response::IntoResponse;
 use axum::Json;
 use axum::{extract::Query, http::StatusCode};
 use serde::{Deserialize, Serialize};
@@ -11,6 +13,11 @@ use tokio::fs;
 use tracing::{error, info};
 use ulid::Ulid;
 
+use crate::api::auth::access::Access;
+use crate::api::auth::{CurrentUser, Scope};
+use crate::api::docker_crud::image::valid_repository;
+use crate::api::docker_crud::image_jobs::push_stream;
+use crate::api::docker_crud::registry::registry_credentials;
 use crate::api::error::ApiError;
 use crate::api::projects::project_management::new_project;
 use crate::api::projects::Project;
@@ -19,13 +26,19 @@ use crate::util::format_webhook_url;
 use crate::SharedAppState;
 
 use super::actions::ActionCommand;
-use super::executor::IoHandleID;
+use super::executor::{IoHandleID, ProjectIoHandle};
 use super::project_management::{pull_project, remove_project};
 use super::{BaseProject, NewProject};
 
 use anyhow::anyhow;
 
-pub async fn list_builds() -> Result<Json<Vec<String>>, ApiError> {
+/// Only the project of an io log, to filter the history without parsing all output.
+#[derive(Deserialize)]
+struct IoLogOwner {
+    project: BaseProject,
+}
+
+pub async fn list_builds(user: CurrentUser) -> Result<Json<Vec<String>>, ApiError> {
     let mut files_iter = fs::read_dir(&PathBuf::from(IO_LOG_FOLDER))
         .await
         .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.into()))?;
@@ -33,6 +46,19 @@ pub async fn list_builds() -> Result<Json<Vec<String>>, ApiError> {
     let mut files: Vec<String> = Vec::new();
 
     while let Ok(Some(f)) = files_iter.next_entry().await {
+        if !user.role.is_global() {
+            let visible = fs::read_to_string(f.path())
+                .await
+                .ok()
+                .and_then(|c| serde_json::from_str::<IoLogOwner>(&c).ok())
+                .is_some_and(|o| {
+                    user.project_access(&o.project.name, Some(&o.project.branch))
+                        .is_some()
+                });
+            if !visible {
+                continue;
+            }
+        }
         files.push(f.file_name().into_string().map_err(|_| {
             ApiError::new(
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -43,8 +69,33 @@ pub async fn list_builds() -> Result<Json<Vec<String>>, ApiError> {
 
     Ok(Json(files))
 }
+/// A finished io log, only for users that can see its project.
+pub async fn io_log_file(
+    user: CurrentUser,
+    Path(file): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // only `{id}.json` names, so the path can never leave the io log folder
+    file.strip_suffix(".json")
+        .and_then(|id| Ulid::from_string(id).ok())
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, anyhow!("no io log {file}")))?;
+
+    let content = fs::read_to_string(PathBuf::from(IO_LOG_FOLDER).join(&file))
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.into()))?;
+    let owner = serde_json::from_str::<IoLogOwner>(&content)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.into()))?;
+    user.require_project(
+        &owner.project.name,
+        Some(&owner.project.branch),
+        Access::View,
+    )?;
+
+    Ok(([(header::CONTENT_TYPE, "application/json")], content))
+}
+
 pub async fn list_current_builds(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
 ) -> Result<Json<BTreeMap<Ulid, BaseProject>>, ApiError> {
     let s;
 
@@ -55,23 +106,32 @@ pub async fn list_current_builds(
     Ok(Json(
         s.into_iter()
             .map(|(k, v)| (k, (v.1).clone()))
+            .filter(|(_, p)| user.project_access(&p.name, Some(&p.branch)).is_some())
             .collect::<BTreeMap<_, _>>(),
     ))
 }
 
 pub async fn pull_project_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
     Query(project): Query<BaseProject>,
 ) -> Result<(StatusCode, Json<Value>), ApiError> {
-    Ok(pull_project(&project.name, &project.branch)
+    user.require_scope(Scope::ProjectsDeploy)?;
+    user.require_project(&project.name, Some(&project.branch), Access::Deploy)?;
+    Ok(pull_project(&project.name, &project.branch, &state.secrets)
         .await
         .map(|_| (StatusCode::CREATED, Json(json!({}))))?)
 }
 
 pub async fn new_project_route(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Json(project_init): Json<NewProject>,
 ) -> Result<(StatusCode, Json<Value>), ApiError> {
-    let path = match new_project(&project_init).await {
+    user.require_scope(Scope::ProjectsWrite)?;
+    // There is no grant for a project that does not exist yet
+    user.require_global()?;
+    let path = match new_project(&project_init, &state.secrets).await {
         Ok(v) => v,
         Err(e) => return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e)),
     };
@@ -118,7 +178,7 @@ pub async fn webhook_route(
         }
     };
 
-    match pull_project(&val.project_name, &val.branch).await {
+    match pull_project(&val.project_name, &val.branch, &state.secrets).await {
         Ok(_) => info!(
             name = &val.project_name,
             branch = &val.branch,
@@ -147,8 +207,11 @@ pub struct ProjectActionReturn {
 pub async fn project_action_route(
     Path((name, branch)): Path<(String, String)>,
     State(mut state): State<SharedAppState>,
+    user: CurrentUser,
     Json(body): Json<ActionCommand>,
 ) -> Result<(StatusCode, Json<ProjectActionReturn>), ApiError> {
+    user.require_scope(Scope::ProjectsDeploy)?;
+    user.require_project(&name, Some(&branch), Access::Deploy)?;
     let mut all_projects = state.projects.get_mut().await;
     let project = match all_projects.get_mut(&name, &branch) {
         Some(a) => a,
@@ -166,12 +229,25 @@ pub async fn project_action_route(
         branch: project.branch.clone(),
     };
     let dir = project.path.clone();
+    let is_build = body.is_build();
 
-    let handle = match body.try_exec(&dir, &base_project, project).await {
+    let mut handle = match body.try_exec(&dir, &base_project, project).await {
         Ok(out) => out,
         Err(e) => return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e)),
     };
 
+    // Runs after the build and only when it succeeded
+    if let Some((image, target)) = project.push_target().filter(|_| is_build) {
+        let credentials = registry_credentials(&state.secrets, &target).await?;
+        let docker = state.docker.lock_owned().await.clone();
+        handle = ProjectIoHandle::from_stream(
+            base_project.clone(),
+            push_stream(docker, image, target.clone(), credentials),
+        )
+        .with_tag(format!("push {target}"))
+        .depends_on(handle);
+    }
+
     let project = handle.project.clone();
     let id = state
         .io_executor
@@ -185,18 +261,64 @@ pub async fn project_action_route(
     ))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PushSetting {
+    /// Repository prefix such as `registry.example.com/team`, `null` stops pushing
+    push_to: Option<String>,
+}
+
+/// Makes every build of a dockerfile project get pushed as `<push_to>/<name>-<branch>:<version>`.
+pub async fn push_setting_route(
+    Path((name, branch)): Path<(String, String)>,
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    Json(setting): Json<PushSetting>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    user.require_scope(Scope::ProjectsWrite)?;
+    user.require_project(&name, Some(&branch), Access::Manage)?;
+    if let Some(push_to) = setting.push_to.as_deref().filter(|p| !valid_repository(p)) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("invalid repository {push_to:?}"),
+        ));
+    }
+    if state.projects.get_owned(&name, &branch).await.is_none() {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("no project registred"),
+        ));
+    }
+
+    state
+        .projects
+        .update(&name, &branch, |p| p.set_push_to(setting.push_to.clone()))
+        .await
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?;
+    info!(?name, ?branch, push_to = ?setting.push_to, username = user.username, "changed push setting");
+
+    Ok((StatusCode::OK, Json(json!({ "push_to": setting.push_to }))))
+}
+
 pub async fn list_projects_route(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
 ) -> Result<(StatusCode, Json<Vec<Project>>), ApiError> {
-    let projects = state.projects.get_all().await.0;
+    let mut projects = state.projects.get_all().await.0;
+    projects.retain(|p| {
+        user.project_access(&p.project_name, Some(&p.branch))
+            .is_some()
+    });
 
     Ok((StatusCode::OK, Json(projects)))
 }
 pub async fn remove_project_route(
     State(state): State<SharedAppState>,
+    user: CurrentUser,
     Query(project): Query<BaseProject>,
 ) -> Result<StatusCode, ApiError> {
-    remove_project(&project.name, &project.branch).await?;
+    user.require_scope(Scope::ProjectsWrite)?;
+    user.require_project(&project.name, Some(&project.branch), Access::Manage)?;
+    remove_project(&project.name, &project.branch, &state.secrets).await?;
     state.projects.remove(&project).await?;
 
     Ok(StatusCode::OK)
@@ -0,0 +1,256 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::{config::WEBHOOK_URL_PATH, SharedAppState};
+
+use super::{auth::CurrentUser, error::ApiError};
+
+const LOGIN_PATH: &str = "/auth/login";
+
+/// Container actions that disrupt as much as a removal, they are limited like `DELETE` requests.
+const DESTRUCTIVE_ACTIONS: [&str; 4] = ["stop", "kill", "restart", "recreate"];
+
+/// Buckets are only cleaned up once there are this many.
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per key, refilled at `per_minute` and holding at most `per_minute` tokens.
+#[derive(Debug)]
+pub struct RateLimiter {
+    name: &'static str,
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    hits: AtomicU64,
+}
+
+impl RateLimiter {
+    /// A limit of 0 disables the limiter.
+    pub fn new(name: &'static str, per_minute: u32) -> Self {
+        Self {
+            name,
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether `key` has a token left, or how long to wait for the next one. Does not take it.
+    pub async fn check(&self, key: &str) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().await;
+        if buckets.len() >= MAX_BUCKETS {
+            // Buckets that filled up again behave the same as new ones
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * per_second < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated).as_secs_f64() * per_second)
+            .min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            return Ok(());
+        }
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+    }
+
+    /// Takes a token for `key` after `check` let it through.
+    pub async fn take(&self, key: &str) {
+        if let Some(bucket) = self.buckets.lock().await.get_mut(key) {
+            bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimits {
+    webhook_ip: RateLimiter,
+    webhook_project: RateLimiter,
+    destructive_ip: RateLimiter,
+    login_ip: RateLimiter,
+    body_too_large: AtomicU64,
+}
+
+impl RateLimits {
+    pub fn new(webhook_ip: u32, webhook_project: u32, destructive_ip: u32, login_ip: u32) -> Self {
+        Self {
+            webhook_ip: RateLimiter::new("webhook_ip", webhook_ip),
+            webhook_project: RateLimiter::new("webhook_project", webhook_project),
+            destructive_ip: RateLimiter::new("destructive_ip", destructive_ip),
+            login_ip: RateLimiter::new("login_ip", login_ip),
+            body_too_large: AtomicU64::new(0),
+        }
+    }
+
+    fn limiters(&self) -> [&RateLimiter; 4] {
+        [
+            &self.webhook_ip,
+            &self.webhook_project,
+            &self.destructive_ip,
+            &self.login_ip,
+        ]
+    }
+}
+
+/// Limits webhook calls per client and per project, logins and destructive requests per client.
+pub async fn rate_limit(
+    State(state): State<SharedAppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let limits = &state.rate_limits;
+    let ip = addr.ip().to_string();
+    let path = req.uri().path();
+
+    let checks: Vec<(&RateLimiter, &str)> = match path.strip_prefix(WEBHOOK_URL_PATH) {
+        Some(project) if req.method() == Method::POST => {
+            vec![
+                (&limits.webhook_ip, &ip),
+                (&limits.webhook_project, project),
+            ]
+        }
+        _ if req.method() == Method::POST && path == LOGIN_PATH => {
+            vec![(&limits.login_ip, &ip)]
+        }
+        _ if is_destructive(req.method(), path) => vec![(&limits.destructive_ip, &ip)],
+        _ => Vec::new(),
+    };
+
+    // Only take tokens once every limit let the request through, a refused request costs nothing
+    for (limiter, key) in &checks {
+        if let Err(wait) = limiter.check(key).await {
+            warn!(limit = limiter.name, key, path, "rate limited");
+            return too_many_requests(wait);
+        }
+    }
+    for (limiter, key) in &checks {
+        limiter.take(key).await;
+    }
+
+    let response = next.run(req).await;
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        limits.body_too_large.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
+
+/// `DELETE` requests and the container actions in `DESTRUCTIVE_ACTIONS`.
+fn is_destructive(method: &Method, path: &str) -> bool {
+    if method == Method::DELETE {
+        return true;
+    }
+    method == Method::POST
+        && path
+            .strip_prefix("/containers/")
+            .and_then(|p| p.split_once('/'))
+            .is_some_and(|(_, action)| DESTRUCTIVE_ACTIONS.contains(&action))
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    // Round up, `Retry-After: 0` would invite an immediate retry
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, seconds.to_string())],
+        Json(json!({ "error": "too many requests" })),
+    )
+        .into_response()
+}
+
+/// Limit hits in the Prometheus text format.
+pub async fn metrics_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+) -> Result<String, ApiError> {
+    user.require_global()?;
+    let limits = &state.rate_limits;
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# HELP servcur_rate_limited_total Requests refused by a rate limit."
+    )?;
+    writeln!(out, "# TYPE servcur_rate_limited_total counter")?;
+    for limiter in limits.limiters() {
+        writeln!(
+            out,
+            "servcur_rate_limited_total{{limit=\"{}\"}} {}",
+            limiter.name,
+            limiter.hits.load(Ordering::Relaxed)
+        )?;
+    }
+    writeln!(
+        out,
+        "# HELP servcur_body_too_large_total Requests refused for exceeding the body size limit."
+    )?;
+    writeln!(out, "# TYPE servcur_body_too_large_total counter")?;
+    writeln!(
+        out,
+        "servcur_body_too_large_total {}",
+        limits.body_too_large.load(Ordering::Relaxed)
+    )?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn container_actions_are_destructive() {
+        assert!(is_destructive(&Method::DELETE, "/volumes/data/remove"));
+        assert!(is_destructive(&Method::POST, "/containers/web/stop"));
+        assert!(is_destructive(&Method::POST, "/containers/web/kill"));
+        assert!(is_destructive(&Method::POST, "/containers/web/restart"));
+        assert!(is_destructive(&Method::POST, "/containers/web/recreate"));
+        assert!(!is_destructive(&Method::POST, "/containers/web/start"));
+        assert!(!is_destructive(&Method::POST, "/containers"));
+        assert!(!is_destructive(&Method::GET, "/containers/web/stop"));
+    }
+
+    #[tokio::test]
+    async fn checking_takes_no_token() {
+        let limiter = RateLimiter::new("test", 1);
+        assert!(limiter.check("a").await.is_ok());
+        assert!(limiter.check("a").await.is_ok());
+        limiter.take("a").await;
+        assert!(limiter.check("a").await.is_err());
+        assert!(limiter.check("b").await.is_ok());
+    }
+}
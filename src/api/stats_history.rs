@@ -0,0 +1,482 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path as UrlPath, Query, State},
+    http::StatusCode,
+    Json,
+};
+use bollard::{container::ListContainersOptions, Docker};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    sync::RwLock,
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    config::{BRANCH_LABEL, PROJECT_LABEL},
+    SharedAppState,
+};
+
+use super::{
+    auth::{access::Access, CurrentUser},
+    docker_crud::{
+        prune::parse_time,
+        stats::{sample_many, ContainerStats},
+    },
+    error::ApiError,
+};
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+/// Step and retention in seconds of each resolution, raw samples first
+const TIERS: [(i64, i64); 3] = [(0, 6 * HOUR), (5 * MINUTE, 7 * DAY), (HOUR, 90 * DAY)];
+/// Samples since the last save are lost when servcur stops
+const SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Usage at one moment, or averaged over a step for downsampled points.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Point {
+    /// Unix seconds, the start of the step for downsampled points
+    pub time: i64,
+    pub cpu_percent: f64,
+    pub memory_usage: u64,
+    pub memory_limit: u64,
+    /// Counters since the container started, the last value of a step
+    pub network_rx: u64,
+    pub network_tx: u64,
+    pub block_read: u64,
+    pub block_write: u64,
+}
+
+impl Point {
+    fn new(time: i64, stats: &ContainerStats) -> Self {
+        Self {
+            time,
+            cpu_percent: stats.cpu_percent,
+            memory_usage: stats.memory_usage,
+            memory_limit: stats.memory_limit,
+            network_rx: stats.network_rx,
+            network_tx: stats.network_tx,
+            block_read: stats.block_read,
+            block_write: stats.block_write,
+        }
+    }
+
+    fn average(points: &[Point], time: i64) -> Self {
+        let n = points.len().max(1);
+        let last = points.last().copied().unwrap_or_default();
+        Self {
+            time,
+            cpu_percent: points.iter().map(|p| p.cpu_percent).sum::<f64>() / n as f64,
+            memory_usage: points.iter().map(|p| p.memory_usage).sum::<u64>() / n as u64,
+            ..last
+        }
+    }
+
+    fn add(&mut self, other: &Point) {
+        self.cpu_percent += other.cpu_percent;
+        self.memory_usage += other.memory_usage;
+        self.memory_limit += other.memory_limit;
+        self.network_rx += other.network_rx;
+        self.network_tx += other.network_tx;
+        self.block_read += other.block_read;
+        self.block_write += other.block_write;
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Tier {
+    points: VecDeque<Point>,
+    /// Samples of the step that is not over yet
+    bucket: Vec<Point>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Series {
+    id: String,
+    name: String,
+    /// Ownership labels, access is still checked after the container is gone
+    labels: HashMap<String, String>,
+    tiers: [Tier; TIERS.len()],
+}
+
+impl Series {
+    /// Downsamples finished steps and drops points past their retention.
+    fn roll(&mut self, now: i64) {
+        for (&(step, retention), tier) in TIERS.iter().zip(&mut self.tiers) {
+            if tier
+                .bucket
+                .first()
+                .is_some_and(|b| b.time / step != now / step)
+            {
+                let start = tier.bucket[0].time / step * step;
+                tier.points.push_back(Point::average(&tier.bucket, start));
+                tier.bucket.clear();
+            }
+            while tier
+                .points
+                .front()
+                .is_some_and(|p| p.time < now - retention)
+            {
+                tier.points.pop_front();
+            }
+        }
+    }
+
+    fn push(&mut self, point: Point) {
+        self.roll(point.time);
+        for (&(step, _), tier) in TIERS.iter().zip(&mut self.tiers) {
+            if step == 0 {
+                tier.points.push_back(point);
+            } else {
+                tier.bucket.push(point);
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tiers
+            .iter()
+            .all(|t| t.points.is_empty() && t.bucket.is_empty())
+    }
+
+    fn last_time(&self) -> i64 {
+        self.tiers[0].points.back().map_or(0, |p| p.time)
+    }
+
+    fn belongs_to(&self, project: &str, branch: &str) -> bool {
+        self.labels.get(PROJECT_LABEL).is_some_and(|p| p == project)
+            && self.labels.get(BRANCH_LABEL).is_some_and(|b| b == branch)
+    }
+
+    fn range(&self, tier: usize, since: i64, until: i64) -> SeriesRange {
+        SeriesRange {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            points: self.tiers[tier]
+                .points
+                .iter()
+                .filter(|p| p.time >= since && p.time <= until)
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+/// Resource usage of every running container over time, one file per container.
+#[derive(Debug, Clone)]
+pub struct StatsHistory {
+    folder: PathBuf,
+    series: Arc<RwLock<HashMap<String, Series>>>,
+}
+
+impl StatsHistory {
+    pub async fn new(folder: &Path) -> Result<Self> {
+        fs::create_dir_all(folder).await?;
+        let mut series = HashMap::new();
+        let mut files = fs::read_dir(folder).await?;
+        while let Some(file) = files.next_entry().await? {
+            let parsed = fs::read_to_string(file.path())
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|c| Ok(serde_json::from_str::<Series>(&c)?));
+            match parsed {
+                Ok(s) => {
+                    series.insert(s.id.clone(), s);
+                }
+                Err(e) => warn!(?e, file = ?file.path(), "skipping unreadable stats history"),
+            }
+        }
+
+        Ok(Self {
+            folder: folder.to_owned(),
+            series: Arc::new(RwLock::new(series)),
+        })
+    }
+
+    /// Samples all running containers every `interval`, runs forever.
+    pub async fn run_sampler(self, docker: Docker, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_save = Instant::now();
+        loop {
+            ticker.tick().await;
+            let containers = match docker
+                .list_containers(None::<ListContainersOptions<String>>)
+                .await
+            {
+                Ok(c) => c,
+                Err(error) => {
+                    warn!(%error, "could not list containers for stats history");
+                    continue;
+                }
+            };
+            let labels: HashMap<String, HashMap<String, String>> = containers
+                .into_iter()
+                .filter_map(|c| Some((c.id?, owner_labels(c.labels.unwrap_or_default()))))
+                .collect();
+            let ids: Vec<String> = labels.keys().cloned().collect();
+            let stats = sample_many(&docker, &ids).await;
+            self.record(Utc::now().timestamp(), stats, labels).await;
+
+            if last_save.elapsed() >= SAVE_INTERVAL {
+                if let Err(e) = self.save().await {
+                    error!(?e, "could not save stats history");
+                }
+                last_save = Instant::now();
+            }
+        }
+    }
+
+    async fn record(
+        &self,
+        now: i64,
+        stats: Vec<ContainerStats>,
+        mut labels: HashMap<String, HashMap<String, String>>,
+    ) {
+        let mut series = self.series.write().await;
+        for s in stats {
+            let entry = series.entry(s.id.clone()).or_insert_with(|| Series {
+                id: s.id.clone(),
+                name: s.name.clone(),
+                labels: HashMap::new(),
+                tiers: Default::default(),
+            });
+            entry.name.clone_from(&s.name);
+            entry.labels = labels.remove(&s.id).unwrap_or_default();
+            entry.push(Point::new(now, &s));
+        }
+
+        // Containers that are gone keep their history until it expires
+        series.values_mut().for_each(|s| s.roll(now));
+        let expired: Vec<String> = series
+            .values()
+            .filter(|s| s.is_empty())
+            .map(|s| s.id.clone())
+            .collect();
+        for id in expired {
+            series.remove(&id);
+            debug!(container = id, "stats history expired");
+            let _ = fs::remove_file(self.folder.join(format!("{id}.json"))).await;
+        }
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let files = self
+            .series
+            .read()
+            .await
+            .values()
+            .map(|s| Ok((format!("{}.json", s.id), serde_json::to_string(s)?)))
+            .collect::<Result<Vec<_>>>()?;
+        for (file, contents) in files {
+            fs::write(self.folder.join(file), contents).await?;
+        }
+        Ok(())
+    }
+}
+
+fn owner_labels(mut labels: HashMap<String, String>) -> HashMap<String, String> {
+    labels.retain(|k, _| k == PROJECT_LABEL || k == BRANCH_LABEL);
+    labels
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryParams {
+    /// A timestamp or an age like `24h`, 24 hours ago when not set
+    since: Option<String>,
+    /// A timestamp or an age, now when not set
+    until: Option<String>,
+}
+
+impl HistoryParams {
+    /// Returns the tier to read from, since and until
+    fn range(&self) -> Result<(usize, i64, i64), ApiError> {
+        let parse = |raw: &Option<String>, default: &str| {
+            parse_time(raw.as_deref().unwrap_or(default))
+                .map(|t| t.timestamp())
+                .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))
+        };
+        let since = parse(&self.since, "24h")?;
+        let until = parse(&self.until, "0s")?;
+        if since > until {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("since must be before until"),
+            ));
+        }
+
+        // The finest resolution that still reaches back to since
+        let now = Utc::now().timestamp();
+        let tier = TIERS
+            .iter()
+            .position(|(_, retention)| now - retention <= since)
+            .unwrap_or(TIERS.len() - 1);
+        Ok((tier, since, until))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesRange {
+    id: String,
+    name: String,
+    points: Vec<Point>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContainerHistory {
+    /// Seconds between points, 0 for raw samples
+    step: i64,
+    #[serde(flatten)]
+    series: SeriesRange,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectHistory {
+    step: i64,
+    /// Sum of all containers per point
+    total: Vec<Point>,
+    containers: Vec<SeriesRange>,
+}
+
+/// `id` can also be a short id or a container name, the most recent container wins.
+pub async fn container_history_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    UrlPath(id): UrlPath<String>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<ContainerHistory>, ApiError> {
+    let (tier, since, until) = params.range()?;
+    let series = state.stats_history.series.read().await;
+    // Hidden containers are left out before picking one, so they neither shadow a visible match nor show up as a 403
+    let found = series
+        .values()
+        .filter(|s| s.id.starts_with(&id) || s.name == id)
+        .filter(|s| user.can_see(Some(&s.labels)))
+        .max_by_key(|s| s.last_time())
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                anyhow!("no stats history for container {id}"),
+            )
+        })?;
+
+    Ok(Json(ContainerHistory {
+        step: TIERS[tier].0,
+        series: found.range(tier, since, until),
+    }))
+}
+
+pub async fn project_history_route(
+    State(state): State<SharedAppState>,
+    user: CurrentUser,
+    UrlPath((name, branch)): UrlPath<(String, String)>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<ProjectHistory>, ApiError> {
+    user.require_project(&name, Some(&branch), Access::View)?;
+    let (tier, since, until) = params.range()?;
+
+    let containers: Vec<SeriesRange> = state
+        .stats_history
+        .series
+        .read()
+        .await
+        .values()
+        .filter(|s| s.belongs_to(&name, &branch))
+        .map(|s| s.range(tier, since, until))
+        .collect();
+
+    // Containers are sampled together, so their points share timestamps
+    let mut total: BTreeMap<i64, Point> = BTreeMap::new();
+    for point in containers.iter().flat_map(|c| &c.points) {
+        total
+            .entry(point.time)
+            .or_insert_with(|| Point {
+                time: point.time,
+                ..Default::default()
+            })
+            .add(point);
+    }
+
+    Ok(Json(ProjectHistory {
+        step: TIERS[tier].0,
+        total: total.into_values().collect(),
+        containers,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series() -> Series {
+        Series {
+            id: "abc".to_owned(),
+            name: "web".to_owned(),
+            labels: HashMap::new(),
+            tiers: Default::default(),
+        }
+    }
+
+    fn point(time: i64, cpu_percent: f64) -> Point {
+        Point {
+            time,
+            cpu_percent,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn finished_steps_are_averaged() {
+        let mut s = series();
+        s.push(point(0, 10.0));
+        s.push(point(60, 30.0));
+        assert!(s.tiers[1].points.is_empty());
+        assert_eq!(s.tiers[1].bucket.len(), 2);
+
+        // The first sample of the next step flushes the bucket
+        s.push(point(5 * MINUTE + 1, 50.0));
+        assert_eq!(s.tiers[0].points.len(), 3);
+        assert_eq!(s.tiers[1].points.len(), 1);
+        assert_eq!(s.tiers[1].points[0].time, 0);
+        assert_eq!(s.tiers[1].points[0].cpu_percent, 20.0);
+        assert_eq!(s.tiers[1].bucket.len(), 1);
+        assert_eq!(s.tiers[2].bucket.len(), 3);
+    }
+
+    #[test]
+    fn points_expire_after_their_retention() {
+        let mut s = series();
+        s.push(point(0, 10.0));
+        s.roll(6 * HOUR);
+        assert_eq!(s.tiers[0].points.len(), 1);
+        s.roll(6 * HOUR + 1);
+        assert!(s.tiers[0].points.is_empty());
+        // The coarser tiers still hold the sample
+        assert_eq!(s.tiers[1].points.len(), 1);
+        assert!(!s.is_empty());
+
+        s.roll(90 * DAY + HOUR);
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn range_is_inclusive() {
+        let mut s = series();
+        for time in [0, 60, 120, 180] {
+            s.push(point(time, 1.0));
+        }
+        let times: Vec<i64> = s.range(0, 60, 120).points.iter().map(|p| p.time).collect();
+        assert_eq!(times, [60, 120]);
+    }
+}
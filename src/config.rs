@@ -1,10 +1,157 @@
+use std::{env, fmt, path::PathBuf, str::FromStr, time::Duration};
+
 use const_format::concatcp;
 
 pub const DATA_FOLDER: &str = "./_data";
 pub const TEMP_SCRIPT_FOLDER: &str = concatcp!(DATA_FOLDER, "/temp/scripts");
+pub const TEMP_DOWNLOAD_FOLDER: &str = concatcp!(DATA_FOLDER, "/temp/downloads");
+pub const TEMP_UPLOAD_FOLDER: &str = concatcp!(DATA_FOLDER, "/temp/uploads");
 pub const PROJECT_FOLDER: &str = concatcp!(DATA_FOLDER, "/projects");
 pub const WEBHOOK_URL_PATH: &str = "/projects/webhook";
+pub const WEBHOOK_ROUTE: &str = concatcp!(WEBHOOK_URL_PATH, "/:name/:branch");
 pub const STORE_LOCATION: &str = concatcp!(DATA_FOLDER, "/store");
 pub const STORE_FILE: &str = "store.json";
+pub const USERS_FILE: &str = "users.json";
+pub const TOKENS_FILE: &str = "tokens.json";
+pub const SECRETS_FILE: &str = "secrets.enc";
+/// Generated password of the bootstrap admin, readable by the owner only
+pub const ADMIN_PASSWORD_FILE: &str = concatcp!(DATA_FOLDER, "/admin_password");
 
 pub const IO_LOG_FOLDER: &str = concatcp!(DATA_FOLDER, "/io");
+pub const AUDIT_FOLDER: &str = concatcp!(DATA_FOLDER, "/audit");
+pub const AUDIT_FILE: &str = "audit.jsonl";
+pub const STATS_FOLDER: &str = concatcp!(DATA_FOLDER, "/stats");
+
+/// Docker labels marking which project (branch) a resource belongs to
+pub const PROJECT_LABEL: &str = "servcur.project";
+pub const BRANCH_LABEL: &str = "servcur.branch";
+
+pub const SESSION_COOKIE: &str = "servcur_session";
+pub const BOOTSTRAP_ADMIN: &str = "admin";
+
+/// Runtime settings, read once from the environment on startup.
+#[derive(Clone)]
+pub struct Settings {
+    /// Only send the session cookie over HTTPS.
+    pub secure_cookies: bool,
+    pub session_ttl: Duration,
+    /// Password for the bootstrap admin, generated when not set.
+    pub admin_password: Option<String>,
+    /// Encrypts the secret store, from `SERVCUR_MASTER_KEY` or the file in `SERVCUR_MASTER_KEY_FILE`.
+    pub master_key: Option<String>,
+    /// Port of the plain HTTP listener, only redirects to HTTPS when TLS is enabled.
+    pub http_port: u16,
+    pub https_port: u16,
+    /// HTTPS is enabled when both `SERVCUR_TLS_CERT` and `SERVCUR_TLS_KEY` are set.
+    pub tls: Option<TlsSettings>,
+    /// Foreign origins (`https://dash.example.com`) allowed to call the API, same-origin only when empty.
+    pub allowed_origins: Vec<String>,
+    /// Requests per minute, 0 disables the limit.
+    pub webhook_ip_limit: u32,
+    pub webhook_project_limit: u32,
+    pub destructive_ip_limit: u32,
+    pub login_ip_limit: u32,
+    pub max_body_bytes: usize,
+    /// Body limit of file uploads into containers.
+    pub max_upload_bytes: usize,
+    /// Shortest interval between live stats messages.
+    pub stats_interval: Duration,
+    /// How often the stats history samples all containers, `None` disables it.
+    pub stats_sample_interval: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+    /// CA that API client certificates must be signed by, enables mutual TLS.
+    pub client_ca: Option<PathBuf>,
+    /// Refuse connections without a client certificate, otherwise it is only verified when sent.
+    pub client_auth_required: bool,
+}
+
+// Keep the passwords out of logs
+impl fmt::Debug for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Settings")
+            .field("secure_cookies", &self.secure_cookies)
+            .field("session_ttl", &self.session_ttl)
+            .field(
+                "admin_password",
+                &self.admin_password.as_ref().map(|_| "<redacted>"),
+            )
+            .field(
+                "master_key",
+                &self.master_key.as_ref().map(|_| "<redacted>"),
+            )
+            .field("http_port", &self.http_port)
+            .field("https_port", &self.https_port)
+            .field("tls", &self.tls)
+            .field("allowed_origins", &self.allowed_origins)
+            .field("webhook_ip_limit", &self.webhook_ip_limit)
+            .field("webhook_project_limit", &self.webhook_project_limit)
+            .field("destructive_ip_limit", &self.destructive_ip_limit)
+            .field("login_ip_limit", &self.login_ip_limit)
+            .field("max_body_bytes", &self.max_body_bytes)
+            .field("max_upload_bytes", &self.max_upload_bytes)
+            .field("stats_interval", &self.stats_interval)
+            .field("stats_sample_interval", &self.stats_sample_interval)
+            .finish()
+    }
+}
+
+impl Settings {
+    pub fn from_env() -> Self {
+        let tls = match (env::var("SERVCUR_TLS_CERT"), env::var("SERVCUR_TLS_KEY")) {
+            (Ok(cert), Ok(key)) => Some(TlsSettings {
+                cert: cert.into(),
+                key: key.into(),
+                client_ca: env::var("SERVCUR_TLS_CLIENT_CA").ok().map(PathBuf::from),
+                client_auth_required: env_or("SERVCUR_TLS_CLIENT_AUTH_REQUIRED", true),
+            }),
+            _ => None,
+        };
+
+        Self {
+            secure_cookies: env_or("SERVCUR_SECURE_COOKIES", tls.is_some()),
+            session_ttl: Duration::from_secs(env_or("SERVCUR_SESSION_TTL_HOURS", 12) * 60 * 60),
+            admin_password: env::var("SERVCUR_ADMIN_PASSWORD").ok(),
+            master_key: env::var("SERVCUR_MASTER_KEY").ok().or_else(|| {
+                let path = env::var("SERVCUR_MASTER_KEY_FILE").ok()?;
+                let key = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Could not read master key file {path}: {e}"));
+                Some(key.trim().to_owned())
+            }),
+            http_port: env_or("SERVCUR_HTTP_PORT", 80),
+            https_port: env_or("SERVCUR_HTTPS_PORT", 443),
+            tls,
+            allowed_origins: env::var("SERVCUR_ALLOWED_ORIGINS")
+                .map(|o| {
+                    o.split(',')
+                        .map(|o| o.trim().trim_end_matches('/').to_owned())
+                        .filter(|o| !o.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            webhook_ip_limit: env_or("SERVCUR_RATE_WEBHOOK_IP", 30),
+            webhook_project_limit: env_or("SERVCUR_RATE_WEBHOOK_PROJECT", 6),
+            destructive_ip_limit: env_or("SERVCUR_RATE_DESTRUCTIVE_IP", 30),
+            login_ip_limit: env_or("SERVCUR_RATE_LOGIN_IP", 10),
+            max_body_bytes: env_or("SERVCUR_MAX_BODY_BYTES", 2 * 1024 * 1024),
+            max_upload_bytes: env_or("SERVCUR_MAX_UPLOAD_BYTES", 512 * 1024 * 1024),
+            stats_interval: Duration::from_secs(env_or("SERVCUR_STATS_INTERVAL_SECS", 2).max(1)),
+            stats_sample_interval: Some(env_or("SERVCUR_STATS_SAMPLE_SECS", 60))
+                .filter(|s| *s > 0)
+                .map(Duration::from_secs),
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
@@ -1,26 +1,45 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use api::auth::session::SessionStore;
+use api::auth::tokens::TokenStore;
+use api::auth::user_store::UserStore;
 use api::docker_crud;
 use api::projects::executor::ProjectIoExecutor;
+use api::projects::project_management::migrate_remote_credentials;
 use api::projects::project_store::ProjectStore;
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
 use axum::response::Redirect;
-use axum::routing::{delete, post};
+use axum::routing::{delete, post, put};
 use axum::{http::StatusCode, routing::get, Router};
 
 use bollard::Docker;
 use store::Store;
 use tokio::sync::Mutex;
-use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
+use tracing::error;
 use tracing_subscriber::EnvFilter;
 
-use crate::config::{IO_LOG_FOLDER, STORE_FILE, STORE_LOCATION};
+use crate::api::audit::{record_mutations, AuditLog};
+use crate::api::auth::middleware::{redirect_to_login, require_session};
+use crate::api::docker_crud::stats::LiveStats;
+use crate::api::origin::{check_origin, cors_layer};
+use crate::api::rate_limit::{rate_limit, RateLimits};
+use crate::api::stats_history::StatsHistory;
+use crate::config::{
+    Settings, AUDIT_FILE, AUDIT_FOLDER, SECRETS_FILE, STATS_FOLDER, STORE_FILE, STORE_LOCATION,
+    TOKENS_FILE, USERS_FILE, WEBHOOK_ROUTE,
+};
+use crate::secrets::SecretStore;
 
 pub mod api;
 pub mod config;
+pub mod secrets;
 pub mod store;
+pub mod tls;
 pub mod util;
 
 #[derive(Debug, Clone)]
@@ -28,6 +47,15 @@ pub struct AppState {
     pub docker: Arc<Mutex<Docker>>,
     pub projects: ProjectStore,
     pub io_executor: Arc<ProjectIoExecutor>,
+    pub users: UserStore,
+    pub sessions: SessionStore,
+    pub tokens: TokenStore,
+    pub audit: AuditLog,
+    pub secrets: SecretStore,
+    pub rate_limits: Arc<RateLimits>,
+    pub stats_history: StatsHistory,
+    pub live_stats: LiveStats,
+    pub settings: Arc<Settings>,
 }
 
 pub type SharedAppState = AppState;
@@ -51,11 +79,59 @@ async fn main() {
         .expect("Could not connect to Docker daemon (is it running?)");
 
     let io_executor = Arc::new(ProjectIoExecutor::new(16));
+    let settings = Settings::from_env();
+
+    let users = UserStore::new(Store::new_str(STORE_LOCATION, USERS_FILE).unwrap()).await;
+    users
+        .bootstrap_admin(settings.admin_password.clone())
+        .await
+        .expect("Could not create bootstrap admin");
+
+    let secrets = SecretStore::new(
+        PathBuf::from(STORE_LOCATION).join(SECRETS_FILE),
+        settings.master_key.as_deref(),
+    )
+    .await
+    .expect("Could not open secret store");
+    let projects = ProjectStore::new(Store::new_str(STORE_LOCATION, STORE_FILE).unwrap()).await;
+    if let Err(e) = migrate_remote_credentials(&projects, &secrets).await {
+        error!(?e, "could not move git credentials out of project remotes");
+    }
+
+    let stats_history = StatsHistory::new(&PathBuf::from(STATS_FOLDER))
+        .await
+        .expect("Could not open stats history");
+    if let Some(interval) = settings.stats_sample_interval {
+        tokio::spawn(stats_history.clone().run_sampler(docker.clone(), interval));
+    }
+
+    let live_stats = LiveStats::new();
+    tokio::spawn(
+        live_stats
+            .clone()
+            .run_sampler(docker.clone(), settings.stats_interval),
+    );
 
     let state: SharedAppState = AppState {
         docker: Arc::new(Mutex::new(docker)),
-        projects: ProjectStore::new(Store::new_str(STORE_LOCATION, STORE_FILE).unwrap()).await,
+        projects,
         io_executor,
+        users,
+        sessions: SessionStore::new(settings.session_ttl),
+        tokens: TokenStore::new(Store::new_str(STORE_LOCATION, TOKENS_FILE).unwrap()).await,
+        audit: AuditLog::new(&PathBuf::from(AUDIT_FOLDER), &PathBuf::from(AUDIT_FILE))
+            .await
+            .expect("Could not open audit log"),
+        secrets,
+        rate_limits: Arc::new(RateLimits::new(
+            settings.webhook_ip_limit,
+            settings.webhook_project_limit,
+            settings.destructive_ip_limit,
+            settings.login_ip_limit,
+        )),
+        stats_history,
+        live_stats,
+        settings: Arc::new(settings.clone()),
     };
 
     let volumes_router = Router::new()
@@ -64,6 +140,7 @@ async fn main() {
         .route("/prune", delete(docker_crud::volume::prune_volumes));
     let containers_router = Router::new()
         .route("/", get(docker_crud::container::containers))
+        .route("/", post(docker_crud::container::create_container))
         .route(
             "/:name/remove",
             delete(docker_crud::container::remove_container),
@@ -77,11 +154,86 @@ async fn main() {
             "/:name/restart",
             post(docker_crud::container::restart_container),
         )
-        .route("/:id/logs", get(api::docker_log_ws::ws_upgrader));
+        .route(
+            "/:name/pause",
+            post(docker_crud::container::pause_container),
+        )
+        .route(
+            "/:name/unpause",
+            post(docker_crud::container::unpause_container),
+        )
+        .route("/:name/kill", post(docker_crud::container::kill_container))
+        .route(
+            "/:name/rename",
+            post(docker_crud::container::rename_container),
+        )
+        .route(
+            "/:name/recreate",
+            post(docker_crud::recreate::recreate_container),
+        )
+        .route(
+            "/:name/update",
+            post(docker_crud::container::update_container),
+        )
+        .route(
+            "/:name/changes",
+            get(docker_crud::container::container_changes),
+        )
+        .route(
+            "/:name/commit",
+            post(docker_crud::container::commit_container),
+        )
+        .route(
+            "/:name/export",
+            get(docker_crud::container::export_container),
+        )
+        .route(
+            "/:name/definition",
+            get(docker_crud::definition::container_definition),
+        )
+        .route("/:name", get(docker_crud::container::inspect_container))
+        .route(
+            "/definition",
+            get(docker_crud::definition::group_definition),
+        )
+        .route("/stats", get(api::docker_stats_ws::containers_ws_upgrader))
+        .route(
+            "/:id/stats",
+            get(api::docker_stats_ws::container_ws_upgrader),
+        )
+        .route("/:id/logs", get(api::docker_log_ws::ws_upgrader))
+        .route("/:id/shell", get(api::docker_exec_ws::ws_upgrader))
+        .route("/:id/exec", post(docker_crud::exec::exec_container))
+        .route("/:id/files", get(docker_crud::files::list_files))
+        .route(
+            "/:id/files",
+            post(docker_crud::files::upload_files)
+                .layer(DefaultBodyLimit::max(settings.max_upload_bytes)),
+        )
+        .route(
+            "/:id/files/download",
+            get(docker_crud::files::download_files),
+        )
+        .route("/prune", delete(docker_crud::container::prune_containers));
     let images_router = Router::new()
         .route("/", get(docker_crud::image::images))
+        .route("/pull", post(docker_crud::image_jobs::pull_image_job))
+        .route("/tag", post(docker_crud::image_jobs::tag_image))
+        .route("/push", post(docker_crud::image_jobs::push_image_job))
+        .route(
+            "/build",
+            post(docker_crud::image_jobs::build_image_job)
+                .layer(DefaultBodyLimit::max(settings.max_upload_bytes)),
+        )
         .route("/:name/remove", delete(docker_crud::image::remove_images))
         .route("/prune", delete(docker_crud::image::prune_images));
+    let registries_router = Router::new()
+        .route("/", get(docker_crud::registry::registries))
+        .route("/", put(docker_crud::registry::set_registry))
+        .route(
+            "/:server_address",
+            delete(docker_crud::registry::remove_registry),
+        );
     let networks_router = Router::new()
         .route("/", get(docker_crud::network::networks))
         .route(
@@ -96,8 +248,8 @@ async fn main() {
         .route("/", delete(api::projects::routes::remove_project_route))
         .route("/pull", get(api::projects::routes::pull_project_route))
         .route(
-            "/webhook/:name/:branch",
-            post(api::projects::routes::webhook_route),
+            "/push/:name/:branch",
+            put(api::projects::routes::push_setting_route),
         )
         .route(
             "/action/:name/:branch",
@@ -115,47 +267,116 @@ async fn main() {
             "/io/history",
             Router::new()
                 .route("/", get(api::projects::routes::list_builds))
-                .nest_service("/files", ServeDir::new(IO_LOG_FOLDER)),
+                .route("/files/:file", get(api::projects::routes::io_log_file)),
         );
 
+    let auth_router = Router::new()
+        .route("/me", get(api::auth::routes::me_route))
+        .route("/logout", post(api::auth::routes::logout_route))
+        .route("/password", post(api::auth::routes::change_password_route))
+        .route("/users", get(api::auth::routes::list_users_route))
+        .route("/users", post(api::auth::routes::new_user_route))
+        .route("/users/:name", delete(api::auth::routes::remove_user_route))
+        .route("/tokens", get(api::auth::routes::list_tokens_route))
+        .route("/tokens", post(api::auth::routes::new_token_route))
+        .route(
+            "/users/:name/access",
+            put(api::auth::routes::user_access_route),
+        )
+        .route("/tokens/:id", delete(api::auth::routes::revoke_token_route));
+
     // Static files
     let serve_dir = ServeDir::new("public/servcur/build")
         .not_found_service(ServeFile::new("public/servcur/build/index.html"));
 
     let static_file_router = Router::new()
         .nest_service("/assets", serve_dir.clone())
-        .fallback_service(serve_dir);
+        .fallback_service(serve_dir)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            redirect_to_login,
+        ));
 
-    // build our application with a route
-    let app = Router::new()
-        // `GET /` goes to `root`
-        .route("/", get(|| async { Redirect::temporary("/app") }))
-        .route("/ping", get(root))
+    // Everything behind a login
+    let protected_router = Router::new()
         .route("/system", get(docker_crud::docker_sys_info))
+        .route("/system/stats", get(api::docker_stats_ws::host_ws_upgrader))
+        .route(
+            "/stats/containers/:id",
+            get(api::stats_history::container_history_route),
+        )
+        .route(
+            "/stats/projects/:name/:branch",
+            get(api::stats_history::project_history_route),
+        )
         .nest("/volumes", volumes_router)
         .nest("/containers", containers_router)
         .nest("/images", images_router)
         .nest("/networks", networks_router)
+        .nest("/registries", registries_router)
         .nest("/projects", projects_router)
+        .nest("/auth", auth_router)
+        .route("/audit", get(api::audit::audit_route))
+        .route("/metrics", get(api::rate_limit::metrics_route))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_session,
+        ));
+
+    // build our application with a route
+    let app = Router::new()
+        // `GET /` goes to `root`
+        .route("/", get(|| async { Redirect::temporary("/app") }))
+        .route("/ping", get(root))
+        .route("/login", get(api::auth::routes::login_page))
+        .route("/auth/login", post(api::auth::routes::login_route))
+        // Webhooks are called by the git host, which has no session
+        .route(WEBHOOK_ROUTE, post(api::projects::routes::webhook_route))
+        .merge(protected_router)
         .nest("/app", static_file_router)
-        .with_state(state)
-        .layer(
-            CorsLayer::new()
-                // allow requests from any origin
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
-        .layer(TraceLayer::new_for_http());
-    // run our app with hyper
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:80").await.unwrap();
-    tracing::debug!("listening on http://{}", listener.local_addr().unwrap());
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
-    .unwrap();
+        .layer(middleware::from_fn_with_state(state.clone(), check_origin))
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .layer(DefaultBodyLimit::max(settings.max_body_bytes))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            record_mutations,
+        ))
+        .with_state(state);
+    let app = match cors_layer(&settings) {
+        Some(cors) => app.layer(cors),
+        None => app,
+    }
+    .layer(TraceLayer::new_for_http());
+    let http_addr = SocketAddr::from(([0, 0, 0, 0], settings.http_port));
+    let Some(tls) = settings.tls else {
+        // run our app with hyper
+        let listener = tokio::net::TcpListener::bind(http_addr).await.unwrap();
+        tracing::debug!("listening on http://{}", listener.local_addr().unwrap());
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+        return;
+    };
+
+    let rustls_config = tls::rustls_config(&tls)
+        .await
+        .expect("Could not load tls certificate");
+    tls::watch_certificates(rustls_config.clone(), tls);
+    tokio::spawn(async move {
+        if let Err(e) = tls::redirect_http(http_addr, settings.https_port).await {
+            error!(?e, "http redirect listener stopped");
+        }
+    });
+
+    let https_addr = SocketAddr::from(([0, 0, 0, 0], settings.https_port));
+    tracing::debug!("listening on https://{https_addr}");
+    axum_server::bind_rustls(https_addr, rustls_config)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
 
 async fn root() -> (StatusCode, &'static str) {
@@ -0,0 +1,196 @@
+use std::{collections::HashMap, fmt, path::PathBuf, sync::Arc};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::Argon2;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncWriteExt, sync::RwLock};
+use tracing::{info, warn};
+
+const SALT_LEN: usize = 16;
+
+/// On-disk layout of the secrets file, the whole map is encrypted at once.
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    /// Salt of the key derivation, files without one were encrypted with a SHA-256 of the master key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Secrets (git credentials, registry passwords) encrypted with AES-256-GCM.
+/// The key is derived from the master key in the settings with Argon2, without one the store stays locked.
+#[derive(Clone)]
+pub struct SecretStore {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    cipher: Option<Aes256Gcm>,
+    inner: Arc<RwLock<HashMap<String, Value>>>,
+}
+
+impl fmt::Debug for SecretStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretStore")
+            .field("path", &self.path)
+            .field("locked", &self.cipher.is_none())
+            .finish()
+    }
+}
+
+impl SecretStore {
+    pub async fn new(path: PathBuf, master_key: Option<&str>) -> Result<Self> {
+        let existing = if fs::try_exists(&path).await? {
+            let raw = fs::read(&path).await?;
+            Some(serde_json::from_slice::<EncryptedFile>(&raw).context("malformed secrets file")?)
+        } else {
+            None
+        };
+        let salt: [u8; SALT_LEN] = match existing.as_ref().and_then(|f| f.salt.as_ref()) {
+            Some(salt) => hex::decode(salt)?
+                .try_into()
+                .map_err(|_| anyhow!("malformed secrets file salt"))?,
+            None => rand::random(),
+        };
+        let cipher = match master_key {
+            Some(key) => Some(derive_cipher(key.to_owned(), salt).await?),
+            None => None,
+        };
+
+        let mut content = HashMap::new();
+        let mut rekey = false;
+        if let Some(file) = &existing {
+            match (master_key, &cipher) {
+                (Some(key), Some(cipher)) => {
+                    content = if file.salt.is_some() {
+                        decrypt(cipher, file)?
+                    } else {
+                        rekey = true;
+                        decrypt(&legacy_cipher(key), file)?
+                    }
+                }
+                _ => warn!(
+                    ?path,
+                    "secrets file exists but no master key is configured, stored credentials are unavailable"
+                ),
+            }
+        }
+
+        let store = Self {
+            path,
+            salt,
+            cipher,
+            inner: Arc::new(RwLock::new(content)),
+        };
+        if rekey {
+            let content = store.inner.read().await;
+            store.persist(store.cipher()?, &content).await?;
+            info!("re-encrypted the secrets file with an argon2 key");
+        }
+        Ok(store)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.inner.read().await.get(key) {
+            Some(v) => Ok(Some(serde_json::from_value(v.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set<T: Serialize>(&self, key: &str, secret: &T) -> Result<()> {
+        let cipher = self.cipher()?;
+        let mut store = self.inner.write().await;
+
+        store.insert(key.to_owned(), serde_json::to_value(secret)?);
+        self.persist(cipher, &store).await
+    }
+
+    /// Stored keys starting with `prefix`, sorted
+    pub async fn keys(&self, prefix: &str) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .inner
+            .read()
+            .await
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    pub async fn remove(&self, key: &str) -> Result<()> {
+        let mut store = self.inner.write().await;
+        if store.remove(key).is_none() {
+            return Ok(());
+        }
+        self.persist(self.cipher()?, &store).await
+    }
+
+    fn cipher(&self) -> Result<&Aes256Gcm> {
+        self.cipher.as_ref().ok_or(anyhow!(
+            "no master key configured, set SERVCUR_MASTER_KEY to store credentials"
+        ))
+    }
+
+    async fn persist(&self, cipher: &Aes256Gcm, content: &HashMap<String, Value>) -> Result<()> {
+        if let Some(folder) = self.path.parent() {
+            fs::create_dir_all(folder).await?;
+        }
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, serde_json::to_vec(content)?.as_slice())
+            .map_err(|_| anyhow!("could not encrypt secrets"))?;
+        let file = EncryptedFile {
+            salt: Some(hex::encode(self.salt)),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        };
+
+        // Written next to the old file and swapped in, so a crash halfway never loses the stored secrets
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let mut out = fs::File::create(&temp).await?;
+        out.write_all(&serde_json::to_vec(&file)?).await?;
+        out.sync_all().await?;
+        fs::rename(&temp, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// Argon2 is deliberately slow, keep it off the async workers
+async fn derive_cipher(master_key: String, salt: [u8; SALT_LEN]) -> Result<Aes256Gcm> {
+    tokio::task::spawn_blocking(move || {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(master_key.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow!("could not derive the secrets key: {e}"))?;
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    })
+    .await?
+}
+
+/// Key of files written before the salt was stored, only used to read them once
+fn legacy_cipher(master_key: &str) -> Aes256Gcm {
+    let key = Sha256::digest(master_key.as_bytes());
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+fn decrypt(cipher: &Aes256Gcm, file: &EncryptedFile) -> Result<HashMap<String, Value>> {
+    let nonce = hex::decode(&file.nonce)?;
+    if nonce.len() != 12 {
+        bail!("malformed secrets file nonce");
+    }
+    let plain = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            hex::decode(&file.ciphertext)?.as_slice(),
+        )
+        .map_err(|_| anyhow!("could not decrypt secrets file, is the master key correct?"))?;
+    Ok(serde_json::from_slice(&plain)?)
+}
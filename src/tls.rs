@@ -0,0 +1,145 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    extract::Host,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Redirect},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    crypto::ring::default_provider,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use tracing::{error, info};
+
+use crate::config::TlsSettings;
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+pub async fn rustls_config(tls: &TlsSettings) -> Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(server_config(tls).await?))
+}
+
+async fn server_config(tls: &TlsSettings) -> Result<Arc<ServerConfig>> {
+    let certs = read_certs(&tls.cert).await?;
+    let key = read_key(&tls.key).await?;
+    let provider = Arc::new(default_provider());
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &tls.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca).await? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if tls.client_auth_required {
+                verifier.build()?
+            } else {
+                verifier.allow_unauthenticated().build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+async fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("could not read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice()).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+async fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let pem = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("could not read {}", path.display()))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())?
+        .ok_or_else(|| anyhow!("no private key found in {}", path.display()))
+}
+
+/// Reloads the certificates when one of the files changes, e.g. after a certbot renewal.
+/// A broken renewal is logged and the previous certificates stay in use.
+pub fn watch_certificates(config: RustlsConfig, tls: TlsSettings) {
+    tokio::spawn(async move {
+        let mut last = modified(&tls).await;
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            let current = modified(&tls).await;
+            if current == last {
+                continue;
+            }
+            last = current;
+
+            match server_config(&tls).await {
+                Ok(c) => {
+                    config.reload_from_config(c);
+                    info!(cert = ?tls.cert, "reloaded tls certificates");
+                }
+                Err(e) => error!(?e, cert = ?tls.cert, "could not reload tls certificates"),
+            }
+        }
+    });
+}
+
+async fn modified(tls: &TlsSettings) -> Vec<Option<SystemTime>> {
+    let mut times = Vec::new();
+    let files: [Option<&PathBuf>; 3] = [Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()];
+    for path in files.into_iter().flatten() {
+        times.push(
+            tokio::fs::metadata(path)
+                .await
+                .and_then(|m| m.modified())
+                .ok(),
+        );
+    }
+    times
+}
+
+/// Plain HTTP listener that only sends clients to the HTTPS port.
+pub async fn redirect_http(addr: SocketAddr, https_port: u16) -> Result<()> {
+    let app = Router::new().fallback(move |host: Option<Host>, uri: Uri| async move {
+        let Some(Host(host)) = host else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        // Drop the port of the plain listener, if any
+        let host = match host.rsplit_once(':') {
+            Some((h, _)) if !host.ends_with(']') => h.to_owned(),
+            _ => host.clone(),
+        };
+        let authority = if https_port == 443 {
+            host
+        } else {
+            format!("{host}:{https_port}")
+        };
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        // 308 keeps the method and body, so webhook posts survive the redirect
+        Redirect::permanent(&format!("https://{authority}{path}")).into_response()
+    });
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("redirecting http://{addr} to https");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
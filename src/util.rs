@@ -1,4 +1,6 @@
 use std::{
+    fs::Permissions,
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
     process::{Output, Stdio},
 };
@@ -11,10 +13,11 @@ use tokio::{
     fs::{create_dir_all, File},
     io::AsyncWriteExt,
     process::Command,
+    sync::OnceCell,
 };
 
 use crate::{
-    api::projects::GitAuth,
+    api::projects::GitCredential,
     config::{PROJECT_FOLDER, TEMP_SCRIPT_FOLDER, WEBHOOK_URL_PATH},
 };
 
@@ -40,21 +43,53 @@ pub async fn upsert_file(folder: &Path, file: &Path, default: &str) -> anyhow::R
     Ok(copy)
 }
 
-pub fn create_git_auth_url(https_url: &str, auth: &GitAuth) -> String {
-    if auth.is_none() {
-        return https_url.to_owned();
-    }
+const GIT_ASKPASS_SCRIPT: &str = r#"#!/bin/sh
+case "$1" in
+    Username*) printf '%s\n' "$SERVCUR_GIT_USERNAME" ;;
+    *) printf '%s\n' "$SERVCUR_GIT_PASSWORD" ;;
+esac
+"#;
 
-    // Prepare url based on auth used
-    let url_end = https_url.replacen("https://", "", 1);
-    let url_auth: &str = match auth {
-        GitAuth::Token(t) => t,
-        _ => panic!(),
-    };
+/// Path of the askpass script, it is written once and only read afterwards.
+static GIT_ASKPASS: OnceCell<PathBuf> = OnceCell::const_new();
 
-    let url = format!("https://{url_auth}@{url_end}");
+/// Concurrent pulls share the script, so it is never rewritten under a running git.
+/// The file is swapped in whole, a leftover from an earlier run may still be executing.
+async fn git_askpass() -> anyhow::Result<&'static PathBuf> {
+    GIT_ASKPASS
+        .get_or_try_init(|| async {
+            create_dir_all(TEMP_SCRIPT_FOLDER).await?;
+            let askpass = PathBuf::from(TEMP_SCRIPT_FOLDER).join("git-askpass.sh");
+            let temp = askpass.with_extension("sh.tmp");
+            tokio::fs::write(&temp, GIT_ASKPASS_SCRIPT).await?;
+            tokio::fs::set_permissions(&temp, Permissions::from_mode(0o700)).await?;
+            tokio::fs::rename(&temp, &askpass).await?;
+            Ok::<_, anyhow::Error>(tokio::fs::canonicalize(&askpass).await?)
+        })
+        .await
+}
 
-    url
+/// Git command that never prompts and receives credentials through `GIT_ASKPASS`,
+/// so they never end up in the remote url, `.git/config` or the process arguments.
+pub async fn git_command(
+    workdir: &Path,
+    credential: Option<&GitCredential>,
+) -> anyhow::Result<Command> {
+    let mut command = Command::new("git");
+    command
+        .current_dir(workdir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        // Don't let a globally configured helper persist the credentials
+        .arg("-c")
+        .arg("credential.helper=");
+
+    if let Some(credential) = credential {
+        command
+            .env("GIT_ASKPASS", git_askpass().await?)
+            .env("SERVCUR_GIT_USERNAME", &credential.username)
+            .env("SERVCUR_GIT_PASSWORD", &credential.password);
+    }
+    Ok(command)
 }
 
 pub fn format_webhook_url(name: &str, branch: &str, absolute: bool) -> String {